@@ -283,6 +283,7 @@ pub fn test_opts(config: &Config) -> test::TestOpts {
         test_shard: config.test_shard.clone(),
         nocapture: false,
         color: test::AutoColor,
+        max_threads: None,
     }
 }
 
@@ -340,7 +341,22 @@ pub fn make_test(config: &Config, testfile: &Path, f: || -> test::TestFn)
         desc: test::TestDesc {
             name: make_test_name(config, testfile),
             ignore: header::is_test_ignored(config, testfile),
-            should_fail: false
+            should_fail: test::No,
+            ignore_reason: None,
+            timeout_ms: None,
+            source_file: "",
+            start_line: 0,
+            iterations: None,
+            groups: &[],
+            description: None,
+            allow_fail: false,
+            retries: 0,
+            module_path: "",
+            weight: 1,
+            serial: false,
+            order_group: None,
+            run_after: &[],
+            bench_unit: None,
         },
         testfn: f(),
     }
@@ -54,6 +54,14 @@ pub enum OptLevel {
     Aggressive // -O3
 }
 
+/// Which of the crate's tests and benchmarks `--test` builds into the harness.
+#[deriving(Clone, PartialEq)]
+pub enum TestHarnessKind {
+    AllTests,
+    TestsOnly, // --no-bench
+    BenchesOnly // --bench
+}
+
 #[deriving(Clone, PartialEq)]
 pub enum DebugInfoLevel {
     NoDebugInfo,
@@ -85,6 +93,18 @@ pub struct Options {
     // anything except building the full crate config prior to parsing.
     pub cfg: ast::CrateConfig,
     pub test: bool,
+    pub test_harness: TestHarnessKind,
+    /// With --test, a file to write a JSON list of the crate's tests to.
+    pub test_manifest: Option<Path>,
+    /// With --test, only tests whose path contains this are built into the
+    /// harness.
+    pub test_filter: Option<String>,
+    /// With --test, only tests of this kind, given by #[test(kind = "...")],
+    /// are built into the harness, along with the benchmarks.
+    pub test_kind: Option<String>,
+    /// With --test, `(k, n)` builds only every nth test of the sorted list,
+    /// starting from the kth, into the harness.
+    pub test_shard: Option<(uint, uint)>,
     pub parse_only: bool,
     pub no_trans: bool,
     pub no_analysis: bool,
@@ -118,6 +138,11 @@ pub fn basic_options() -> Options {
         target_triple: driver::host_triple().to_string(),
         cfg: Vec::new(),
         test: false,
+        test_harness: AllTests,
+        test_manifest: None,
+        test_filter: None,
+        test_kind: None,
+        test_shard: None,
         parse_only: false,
         no_trans: false,
         no_analysis: false,
@@ -188,7 +213,15 @@ debugging_opts!(
         FLOWGRAPH_PRINT_LOANS,
         FLOWGRAPH_PRINT_MOVES,
         FLOWGRAPH_PRINT_ASSIGNS,
-        FLOWGRAPH_PRINT_ALL
+        FLOWGRAPH_PRINT_ALL,
+        KEEP_INVALID_TESTS,
+        LIST_IGNORED_TESTS,
+        TEST_REGISTRY,
+        UNHYGIENIC_TEST_REEXPORTS,
+        QUALIFY_TEST_NAMES,
+        SIZED_TEST_ARRAY,
+        TEST_RUN_ONE,
+        SORT_TEST_NAMES
     ]
     0
 )
@@ -232,7 +265,26 @@ pub fn debugging_opts_map() -> Vec<(&'static str, &'static str, u64)> {
      ("flowgraph-print-assigns", "Include assignment analysis data in \
                        --pretty flowgraph output", FLOWGRAPH_PRINT_ASSIGNS),
      ("flowgraph-print-all", "Include all dataflow analysis data in \
-                       --pretty flowgraph output", FLOWGRAPH_PRINT_ALL))
+                       --pretty flowgraph output", FLOWGRAPH_PRINT_ALL),
+     ("keep-invalid-tests", "With --test, list tests with an invalid \
+                       signature as ignored rather than rejecting them", KEEP_INVALID_TESTS),
+     ("list-ignored-tests", "With --test, print a note listing the \
+                       ignored tests", LIST_IGNORED_TESTS),
+     ("test-registry", "With --test, export a `__rust_test_registry` function \
+                       giving the crate's tests", TEST_REGISTRY),
+     ("unhygienic-test-reexports", "With --test, name the modules reexporting \
+                       tests `__test_reexports` rather than using a fresh \
+                       identifier, so the expanded harness is reproducible. \
+                       Crates can clash with these names, so this is only for \
+                       inspecting the harness", UNHYGIENIC_TEST_REEXPORTS),
+     ("qualify-test-names", "With --test, prefix the names the tests are run \
+                       under with the crate's name", QUALIFY_TEST_NAMES),
+     ("sized-test-array", "With --test, keep the tests in a fixed-size array, \
+                       `TESTS_ARRAY`, which `TESTS` is a slice of", SIZED_TEST_ARRAY),
+     ("test-run-one", "With --test, give the test module a `run_one` function \
+                       running a single test by name", TEST_RUN_ONE),
+     ("sort-test-names", "With --test, list the tests sorted by path rather \
+                       than in the order they're found in", SORT_TEST_NAMES))
 }
 
 /// Declare a macro that will define all CodegenOptions fields and parsers all
@@ -564,6 +616,20 @@ pub fn optgroups() -> Vec<getopts::OptGroup> {
                   in a format suitable for use by Makefiles", "FILENAME"),
         optopt("", "sysroot", "Override the system root", "PATH"),
         optflag("", "test", "Build a test harness"),
+        optflag("", "bench", "With --test, build only the benchmarks into \
+                              the test harness"),
+        optflag("", "no-bench", "With --test, leave the benchmarks out of \
+                                 the test harness"),
+        optopt("", "test-manifest", "With --test, write a JSON list of the \
+                                     tests in the harness to FILENAME", "FILENAME"),
+        optopt("", "test-filter", "With --test, only build the tests whose \
+                                   path contains SUBSTR into the test harness", "SUBSTR"),
+        optopt("", "test-kind", "With --test, only build the tests of kind KIND, \
+                                 such as `unit` or `integration`, into the test \
+                                 harness", "KIND"),
+        optopt("", "test-shard", "With --test, split the tests into N shards by \
+                                  their sorted position, and only build shard \
+                                  K, counting from 0, into the test harness", "K/N"),
         optopt("", "target", "Target triple cpu-manufacturer-kernel[-os]
                             to compile for (see chapter 3.4 of http://www.sourceware.org/autobook/
                             for details)", "TRIPLE"),
@@ -722,6 +788,26 @@ pub fn build_session_options(matches: &getopts::Matches) -> Options {
 
     let cfg = parse_cfgspecs(matches.opt_strs("cfg"));
     let test = matches.opt_present("test");
+    let test_harness = match (matches.opt_present("bench"),
+                              matches.opt_present("no-bench")) {
+        (false, false) => AllTests,
+        (true, false) => BenchesOnly,
+        (false, true) => TestsOnly,
+        (true, true) => early_error("--bench and --no-bench cannot be used \
+                                     together"),
+    };
+    let test_manifest = matches.opt_str("test-manifest").map(|p| Path::new(p));
+    let test_filter = matches.opt_str("test-filter");
+    let test_kind = matches.opt_str("test-kind");
+    let test_shard = matches.opt_str("test-shard").map(|s| {
+        let parts: Vec<Option<uint>> = s.as_slice().split('/').map(|n| from_str(n)).collect();
+        match parts.as_slice() {
+            [Some(k), Some(n)] if k < n => (k, n),
+            _ => early_error(format!("--test-shard must be given `K/N`, with shard \
+                                      K counted from 0 and less than N, not `{}`",
+                                     s).as_slice()),
+        }
+    });
     let write_dependency_info = (matches.opt_present("dep-info"),
                                  matches.opt_str("dep-info")
                                         .map(|p| Path::new(p)));
@@ -779,6 +865,11 @@ pub fn build_session_options(matches: &getopts::Matches) -> Options {
         target_triple: target,
         cfg: cfg,
         test: test,
+        test_harness: test_harness,
+        test_manifest: test_manifest,
+        test_filter: test_filter,
+        test_kind: test_kind,
+        test_shard: test_shard,
         parse_only: parse_only,
         no_trans: no_trans,
         no_analysis: no_analysis,
@@ -69,6 +69,7 @@ static KNOWN_FEATURES: &'static [(&'static str, Status)] = &[
     ("rustc_diagnostic_macros", Active),
     ("unboxed_closures", Active),
     ("import_shadowing", Active),
+    ("cfg_attr", Active),
 
     // if you change this list without updating src/doc/rust.md, cmr will be sad
 
@@ -364,6 +365,11 @@ impl<'a> Visitor<()> for Context<'a> {
                               attr.span,
                               "language items are subject to change");
         }
+        if attr::contains_name([*attr], "cfg_attr") {
+            self.gate_feature("cfg_attr",
+                              attr.span,
+                              "`#[cfg_attr]` is experimental and subject to change");
+        }
     }
 
     fn visit_fn(&mut self,
@@ -13,15 +13,23 @@
 #![allow(dead_code)]
 #![allow(unused_imports)]
 
+use driver::config::{KEEP_INVALID_TESTS, LIST_IGNORED_TESTS, TEST_REGISTRY};
+use driver::config::{UNHYGIENIC_TEST_REEXPORTS, QUALIFY_TEST_NAMES, SIZED_TEST_ARRAY};
+use driver::config::{TEST_RUN_ONE, SORT_TEST_NAMES};
+use driver::config::{AllTests, TestsOnly, BenchesOnly};
 use driver::session::Session;
 use front::config;
 
+use serialize::{json, Encodable};
+use std::ascii::AsciiExt;
+use std::char;
+use std::collections::{HashMap, HashSet};
 use std::gc::{Gc, GC};
+use std::io::File;
 use std::slice;
-use std::mem;
 use std::vec;
 use syntax::ast_util::*;
-use syntax::attr::AttrMetaMethods;
+use syntax::attr::{AttrMetaMethods, AttributeMethods};
 use syntax::attr;
 use syntax::codemap::{DUMMY_SP, Span, ExpnInfo, NameAndSpan, MacroAttribute};
 use syntax::codemap;
@@ -36,13 +44,67 @@ use syntax::parse::token;
 use syntax::print::pprust;
 use syntax::{ast, ast_util};
 use syntax::util::small_vector::SmallVector;
+use syntax::visit::Visitor;
+use syntax::visit;
+
+#[deriving(PartialEq)]
+pub enum ShouldFail {
+    No,
+    Yes,
+    // any one of the messages will do
+    YesWithMessage(Vec<InternedString>),
+}
 
-struct Test {
-    span: Span,
-    path: Vec<ast::Ident> ,
-    bench: bool,
-    ignore: bool,
-    should_fail: bool
+/// A test or benchmark found in the crate, as it will be described to the
+/// test runner.
+pub struct Test {
+    pub span: Span,
+    pub path: Vec<ast::Ident> ,
+    pub name: Option<InternedString>,
+    pub bench: bool,
+    // a #[bench(as_test)] benchmark, run once as a test
+    pub bench_as_test: bool,
+    pub ignore: bool,
+    pub ignore_reason: Option<InternedString>,
+    pub should_fail: ShouldFail,
+    pub returns_value: bool,
+    // declared as returning a `Result`, which a #[should_fail] test's `Err`
+    // can't stand in for failing
+    pub returns_result: bool,
+    // #[should_err]: the test is expected to return an `Err`
+    pub should_err: bool,
+    // declared as `fn() -> !`
+    pub diverges: bool,
+    pub timeout_ms: Option<u64>,
+    pub iterations: Option<uint>,
+    pub groups: Vec<InternedString>,
+    pub description: Option<InternedString>,
+    pub allow_fail: bool,
+    pub retries: uint,
+    pub weight: uint,
+    // #[test(serial)]: not to be run alongside any other test
+    pub serial: bool,
+    // #[test(group = "...")]: the group the test is ordered with
+    pub order_group: Option<InternedString>,
+    // #[test(after = "...")]: the groups whose tests run before this one
+    pub run_after: Vec<InternedString>,
+    // #[test(kind = "...")]: what sort of test this is, "unit" by default,
+    // for --test-kind to pick out
+    pub kind: InternedString,
+    // #[bench(unit = "...")]: what the benchmark's throughput is given in
+    pub bench_unit: Option<InternedString>,
+    // for a #[test(fixture = "...")] test, the path to the fixture function
+    // from the reexport module, through the alias it's reexported as
+    pub fixture: Option<Vec<ast::Ident>>,
+    pub is_method: bool,
+    // for a test declared in a trait, or in an impl of a type brought in
+    // with `use`, the free function copied from it, which is what's called
+    pub called_as: Option<ast::Ident>,
+    // the source file of the module the test is in, which for a module
+    // declared with #[path = "..."] is the file it was loaded from
+    pub module_file: Option<String>,
+    // kept with -Z keep-invalid-tests despite having the wrong signature
+    pub invalid_signature: bool,
 }
 
 struct TestCtxt<'a> {
@@ -52,12 +114,25 @@ struct TestCtxt<'a> {
     testfns: Vec<Test>,
     reexport_mod_ident: ast::Ident,
     reexport_test_harness_main: Option<InternedString>,
+    // the crate providing the test runner, `test` unless overridden by
+    // #[test_runner_crate = "..."]
+    runner_crate: ast::Ident,
+    harness: HarnessOptions,
+    // the name given by #![crate_name = "..."], if any
+    crate_name: Option<InternedString>,
     is_test_crate: bool,
+    // whether the crate brings its own entry point, with a #[start]
+    // function or #![no_main], so the harness's main mustn't be #[main]
+    has_own_entry: bool,
+    // #![no_std], so the harness can't name anything in `std`
+    is_no_std: bool,
     config: ast::CrateConfig,
 }
 
 // Traverse the crate, collecting all the test functions, eliding any
-// existing main functions, and synthesizing a main test harness
+// existing main functions, and synthesizing a main test harness. This runs
+// after the crate's items have been stripped by their #[cfg]s, so a test
+// whose cfg is inactive is never seen, and isn't reexported.
 pub fn modify_for_testing(sess: &Session,
                           krate: ast::Crate) -> ast::Crate {
     // We generate the test harness when building in the 'test'
@@ -73,29 +148,198 @@ pub fn modify_for_testing(sess: &Session,
         attr::first_attr_value_str_by_name(krate.attrs.as_slice(),
                                            "reexport_test_harness_main");
 
+    // Check for #[test_runner_crate = "some_crate"], which links the
+    // harness against a replacement for the `test` crate. This is also
+    // read unconditionally for the same reason.
+    let test_runner_crate =
+        attr::first_attr_value_str_by_name(krate.attrs.as_slice(),
+                                           "test_runner_crate");
+
+    // Check for #[test_harness(...)], for the same reason.
+    let harness = harness_options(sess, &krate);
+
     if should_test {
-        generate_test_harness(sess, reexport_test_harness_main, krate)
+        let reexport_test_harness_main =
+            reexport_test_harness_main.filtered(|path| check_reexport_path(sess, &krate, path));
+        generate_test_harness(sess, reexport_test_harness_main,
+                              test_runner_crate, harness, krate)
     } else {
         strip_test_functions(krate)
     }
 }
 
+// Checks that the path given by #![reexport_test_harness_main = "..."] is
+// made of identifiers, as it's turned into a `use` without being parsed.
+fn check_reexport_path(sess: &Session, krate: &ast::Crate, path: &InternedString) -> bool {
+    if path.get().split_str("::").all(|segment| is_ident(segment)) {
+        return true;
+    }
+    let span = krate.attrs.iter()
+                          .find(|attr| attr.check_name("reexport_test_harness_main"))
+                          .map_or(krate.span, |attr| attr.span);
+    sess.span_err(span, format!("`{}` is not a valid name to reexport the test harness's \
+                                 main function as", path.get()).as_slice());
+    false
+}
+
+// Whether `s` can be used as an identifier: it isn't a keyword, and starts
+// with a letter or `_`.
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    let start = match chars.next() {
+        Some(c) => c == '_' || char::is_XID_start(c),
+        None => false
+    };
+    start && chars.all(|c| char::is_XID_continue(c)) &&
+        !token::is_any_keyword(&token::IDENT(token::str_to_ident(s), false))
+}
+
+/// Finds the tests and benchmarks in `krate` the same way `--test` does,
+/// without generating a harness for them. Malformed tests are reported
+/// through `sess`, as they would be when building the harness.
+pub fn collect_tests(sess: &Session, krate: &ast::Crate) -> Vec<Test> {
+    let test_runner_crate =
+        attr::first_attr_value_str_by_name(krate.attrs.as_slice(),
+                                           "test_runner_crate");
+    let harness = harness_options(sess, krate);
+    let mut fold = TestHarnessGenerator {
+        cx: mk_test_ctxt(sess, None, test_runner_crate, harness, krate),
+        modules: Vec::new(),
+        ignored_modules: Vec::new(),
+    };
+    // Folding the crate's contents directly skips `fold_crate`, which is
+    // where the harness itself is built.
+    fold.cx.ext_cx.bt_push(test_expn_info());
+    fold::noop_fold_crate(krate.clone(), &mut fold);
+    fold.cx.ext_cx.bt_pop();
+    fold.cx.testfns
+}
+
 struct TestHarnessGenerator<'a> {
     cx: TestCtxt<'a>,
+    // one for each module being folded, innermost last
+    modules: Vec<ModuleTests>,
+    // the reasons given by each enclosing module marked #[ignore], which
+    // ignores all of the tests inside it
+    ignored_modules: Vec<Option<InternedString>>,
+}
+
+// What a module's reexport module has to reexport, gathered while the
+// module is folded.
+struct ModuleTests {
     tests: Vec<ast::Ident>,
     tested_submods: Vec<ast::Ident>,
+    // the names of the items defined in the module, which an impl's tests
+    // can be reexported through
+    item_names: Vec<ast::Name>,
+    // the fixtures used by tests in the module, each with the alias it's
+    // reexported as and its path from the module
+    fixtures: Vec<(ast::Ident, Vec<ast::Ident>)>,
+    // the file the module's contents come from, unless they were made up
+    // by the compiler
+    file: Option<String>,
 }
 
 impl<'a> fold::Folder for TestHarnessGenerator<'a> {
     fn fold_crate(&mut self, c: ast::Crate) -> ast::Crate {
         let mut folded = fold::noop_fold_crate(c, self);
 
+        check_duplicate_tests(&self.cx);
+        check_case_collisions(&self.cx);
+        check_test_order(&self.cx);
+
+        // A harness with nothing in it would report "0 tests" as a pass. Tests
+        // which were rejected with an error have been reported already.
+        if self.cx.testfns.is_empty() && !self.cx.is_test_crate &&
+           !self.cx.sess.has_errors() {
+            self.cx.sess.span_warn(folded.span,
+                                   "no tests were found in this crate; are \
+                                    `#[test]` attributes missing, or were the \
+                                    tests removed by a `#[cfg]`?");
+        }
+        // Likewise --bench on a crate which has tests, but no benchmarks,
+        // would run nothing
+        if self.cx.sess.opts.test_harness == BenchesOnly && !self.cx.testfns.is_empty() &&
+           !self.cx.testfns.iter().any(|test| test.bench) && !self.cx.sess.has_errors() {
+            self.cx.sess.span_warn(folded.span,
+                                   "no benchmarks were found in this crate; are \
+                                    `#[bench]` attributes missing?");
+        }
+
+        // --bench and --no-bench build only one kind of test into the harness
+        match self.cx.sess.opts.test_harness {
+            AllTests => {}
+            TestsOnly => self.cx.testfns.retain(|test| !test.bench),
+            BenchesOnly => self.cx.testfns.retain(|test| test.bench),
+        }
+
+        // --test-filter leaves out the tests whose path doesn't match, and
+        // TEST_COUNT counts only those which are left
+        match self.cx.sess.opts.test_filter {
+            Some(ref filter) => self.cx.testfns.retain(|test| {
+                ast_util::path_name_i(test.path.as_slice()).as_slice()
+                                                           .contains(filter.as_slice())
+            }),
+            None => {}
+        }
+
+        // --test-kind leaves out the tests of other kinds, but not the
+        // benchmarks, which --bench and --no-bench pick between
+        match self.cx.sess.opts.test_kind {
+            Some(ref kind) => self.cx.testfns.retain(|test| {
+                test.bench || test.kind.get() == kind.as_slice()
+            }),
+            None => {}
+        }
+
+        // --test-shard K/N leaves in the tests whose position in the list
+        // sorted by path is K modulo N, whether they're tests or benchmarks
+        match self.cx.sess.opts.test_shard {
+            Some((shard, shards)) => {
+                // the tests are left in the order they were found in, unless
+                // -Z sort-test-names sorts them
+                let paths: Vec<String> = self.cx.testfns.iter().map(|test| {
+                    ast_util::path_name_i(test.path.as_slice())
+                }).collect();
+                let mut by_path: Vec<uint> = range(0, paths.len()).collect();
+                by_path.sort_by(|&a, &b| paths.get(a).cmp(paths.get(b)));
+                let mut keep = Vec::from_elem(paths.len(), false);
+                for (position, &index) in by_path.iter().enumerate() {
+                    *keep.get_mut(index) = position % shards == shard;
+                }
+                let mut index = 0u;
+                self.cx.testfns.retain(|_| {
+                    index += 1;
+                    *keep.get(index - 1)
+                });
+            }
+            None => {}
+        }
+
+        match self.cx.sess.opts.test_manifest {
+            Some(ref path) => write_test_manifest(&self.cx, path),
+            None => {}
+        }
+        note_ignored_tests(&self.cx);
+        if self.cx.sess.debugging_opt(QUALIFY_TEST_NAMES) && crate_name(&self.cx).is_none() {
+            self.cx.sess.warn("-Z qualify-test-names needs the crate's name, from \
+                               `#![crate_name = \"...\"]` or --crate-name; the \
+                               test names are left as they are");
+        }
+
         // Add a special __test module to the crate that will contain code
         // generated for the test harness
         let (mod_, reexport) = mk_test_module(&self.cx, &self.cx.reexport_test_harness_main);
         folded.module.items.push(mod_);
         match reexport {
-            Some(re) => folded.module.view_items.push(re),
+            Some((mod_path, re)) => {
+                if !add_view_item(&mut folded.module, mod_path.as_slice(), re) {
+                    let path = ast_util::path_name_i(mod_path.as_slice());
+                    self.cx.sess.err(format!("cannot reexport the test harness's main \
+                                              function into `{}`, which isn't a module",
+                                             path).as_slice());
+                }
+            }
             None => {}
         }
         folded
@@ -106,227 +350,1628 @@ impl<'a> fold::Folder for TestHarnessGenerator<'a> {
         debug!("current path: {}",
                ast_util::path_name_i(self.cx.path.as_slice()));
 
-        if is_test_fn(&self.cx, i) || is_bench_fn(&self.cx, i) {
-            match i.node {
-                ast::ItemFn(_, ast::UnsafeFn, _, _, _) => {
-                    let sess = self.cx.sess;
-                    sess.span_fatal(i.span,
-                                    "unsafe functions cannot be used for \
-                                     tests");
+        let mut copies = Vec::new();
+        match i.node {
+            ast::ItemFn(..) => {
+                if attr::contains_name(i.attrs.as_slice(), "start") {
+                    self.cx.has_own_entry = true;
                 }
-                _ => {
-                    debug!("this is a test function");
-                    let test = Test {
-                        span: i.span,
-                        path: self.cx.path.clone(),
-                        bench: is_bench_fn(&self.cx, i),
-                        ignore: is_ignored(&self.cx, i),
-                        should_fail: should_fail(i)
-                    };
-                    self.cx.testfns.push(test);
-                    self.tests.push(i.ident);
+                let path = self.cx.path.clone();
+                if self.collect_test(i, path, false) {
+                    self.current_module().tests.push(i.ident);
                     // debug!("have {} test/bench functions",
                     //        cx.testfns.len());
                 }
             }
+            ast::ItemImpl(..) => {
+                warn_misplaced_test_attrs(&self.cx, i);
+                copies = self.collect_impl_tests(i);
+            }
+            ast::ItemTrait(..) => {
+                warn_misplaced_test_attrs(&self.cx, i);
+                copies = self.collect_trait_tests(i);
+            }
+            _ => warn_misplaced_test_attrs(&self.cx, i),
         }
 
         // We don't want to recurse into anything other than mods, since
-        // mods or tests inside of functions will break things
-        let res = match i.node {
-            ast::ItemMod(..) => fold::noop_fold_item(&*i, self),
-            _ => SmallVector::one(i),
+        // mods or tests inside of functions will break things, so tests in
+        // there are reported instead
+        let mut res = match i.node {
+            ast::ItemMod(..) => {
+                let (ignore, reason) = is_ignored(&self.cx, i);
+                if ignore {
+                    self.ignored_modules.push(reason);
+                }
+                let res = fold::noop_fold_item(&*i, self);
+                if ignore {
+                    self.ignored_modules.pop();
+                }
+                res
+            }
+            _ => {
+                visit::walk_item(&mut NestedTestVisitor { sess: self.cx.sess }, &*i, ());
+                SmallVector::one(i)
+            }
         };
+        // the functions copied from a trait's or impl's tests go alongside it
+        for copy in copies.move_iter() {
+            res.push(copy);
+        }
         self.cx.path.pop();
         res
     }
 
-    fn fold_mod(&mut self, m: &ast::Mod) -> ast::Mod {
-        let tests = mem::replace(&mut self.tests, Vec::new());
-        let tested_submods = mem::replace(&mut self.tested_submods, Vec::new());
-        let mut mod_folded = fold::noop_fold_mod(m, self);
-        let tests = mem::replace(&mut self.tests, tests);
-        let tested_submods = mem::replace(&mut self.tested_submods, tested_submods);
+    fn fold_mod(&mut self, m: &ast::Mod) -> ast::Mod {
+        self.modules.push(ModuleTests {
+            tests: Vec::new(),
+            tested_submods: Vec::new(),
+            item_names: m.items.iter().map(|item| item.ident.name).collect(),
+            fixtures: Vec::new(),
+            file: module_file(&self.cx, m),
+        });
+        let mut mod_folded = fold::noop_fold_mod(m, self);
+        let mut module = self.modules.pop().unwrap();
+        self.check_reexported_tests(&mut module, mod_folded.items.as_slice());
+
+        // Remove any #[main] from the AST so it doesn't clash with
+        // the one we're going to add. Only if compiling an executable.
+        // A #[start] function is left alone, and takes the place of the
+        // harness's main.
+        for i in mod_folded.items.mut_iter() {
+            *i = nomain(*i);
+        }
+        if !module.tests.is_empty() || !module.tested_submods.is_empty() {
+            mod_folded.items.push(mk_reexport_mod(&mut self.cx, module));
+            // the crate root has no parent to reexport it
+            let ident = self.cx.path.last().map(|ident| *ident);
+            match (self.modules.mut_last(), ident) {
+                (Some(parent), Some(ident)) => parent.tested_submods.push(ident),
+                _ => {}
+            }
+        }
+
+        mod_folded
+    }
+}
+
+impl<'a> TestHarnessGenerator<'a> {
+    fn current_module(&mut self) -> &mut ModuleTests {
+        self.modules.mut_last().expect("test collected outside of a module")
+    }
+
+    // The reexport module names each of the module's tests, so a test which
+    // is no longer among the module's items would only show up as a
+    // confusing unresolved import in the harness. Report it against the
+    // test instead, and leave it out. Only test functions are checked: the
+    // other names reexported are impl self types, which were found among
+    // the module's items, and copies the harness made itself.
+    fn check_reexported_tests(&mut self, module: &mut ModuleTests, items: &[Gc<ast::Item>]) {
+        let mut missing = Vec::new();
+        {
+            let cx = &self.cx;
+            module.tests.retain(|ident| {
+                if items.iter().any(|item| item.ident.name == ident.name) {
+                    return true;
+                }
+                let mut path = cx.path.clone();
+                path.push(*ident);
+                let span = cx.testfns.iter().find(|test| test.path == path).map(|test| test.span);
+                match span {
+                    Some(span) => {
+                        missing.push((path, span));
+                        false
+                    }
+                    None => true
+                }
+            });
+        }
+        for (path, span) in missing.move_iter() {
+            self.cx.sess.span_err(span,
+                                  format!("test `{}` was removed from its module before \
+                                           the test harness was built",
+                                          ast_util::path_name_i(path.as_slice())).as_slice());
+            self.cx.testfns.retain(|test| test.path != path);
+        }
+    }
+
+    // Records `i` if it is a test or bench function, returning whether it
+    // was one.
+    fn collect_test(&mut self, i: Gc<ast::Item>, path: Vec<ast::Ident>,
+                    is_method: bool) -> bool {
+        // the test and bench signatures can't both be satisfied, so don't
+        // report the mismatch against each of them
+        let attrs = i.attrs.as_slice();
+        if attr::contains_name(attrs, "test") && attr::contains_name(attrs, "bench") {
+            self.cx.sess.span_err(i.span, "a function cannot be both a test and a benchmark");
+            return false;
+        }
+
+        // each of these checks reports a malformed signature, so they're
+        // only run once
+        let is_test = is_test_fn(&self.cx, i);
+        let is_bench = is_bench_fn(&self.cx, i);
+        if !is_test && !is_bench {
+            self.collect_invalid_test(i, path);
+            return false;
+        }
+
+        match i.node {
+            ast::ItemFn(_, ast::UnsafeFn, _, _, _) => {
+                let sess = self.cx.sess;
+                sess.span_err(i.span,
+                              "unsafe functions cannot be used for \
+                               tests");
+                false
+            }
+            _ => {
+                debug!("this is a test function");
+                let (mut ignore, mut ignore_reason) = is_ignored(&self.cx, i);
+                if !self.ignored_modules.is_empty() {
+                    ignore = true;
+                    if ignore_reason.is_none() {
+                        ignore_reason = self.ignored_modules.iter().rev()
+                                            .filter_map(|reason| reason.clone()).next();
+                    }
+                }
+                if !ignore && self.is_default_ignored(i, path.as_slice()) {
+                    ignore = true;
+                }
+                let as_test = is_bench && bench_as_test(i.attrs.as_slice());
+                // the fixture is reexported alongside the test, under an
+                // alias which can't clash with anything in the module
+                let fixture = test_fixture(&self.cx, i).map(|fixture_path| {
+                    let alias = token::gensym_ident("__fixture");
+                    self.current_module().fixtures.push((alias, fixture_path));
+                    let mut path = self.cx.path.init().to_vec();
+                    path.push(alias);
+                    path
+                });
+                note_redundant_cfg_test(&self.cx, i);
+                let should_fail = should_fail(&self.cx, i);
+                let returns_result = returns_result(i);
+                let should_err = should_err(&self.cx, i, &should_fail, returns_result);
+                let test = Test {
+                    span: i.span,
+                    path: path,
+                    name: test_name(&self.cx, i),
+                    bench: is_bench && !as_test,
+                    bench_as_test: as_test,
+                    ignore: ignore,
+                    ignore_reason: ignore_reason,
+                    should_fail: should_fail,
+                    returns_value: returns_value(i),
+                    returns_result: returns_result,
+                    should_err: should_err,
+                    diverges: diverges(i),
+                    timeout_ms: test_timeout(&self.cx, i),
+                    iterations: bench_iterations(&self.cx, i),
+                    groups: test_groups(&self.cx, i),
+                    description: test_description(i),
+                    allow_fail: attr::contains_name(i.attrs.as_slice(), "allow_fail"),
+                    retries: test_retries(&self.cx, i),
+                    weight: test_weight(&self.cx, i),
+                    serial: test_meta_item(i, "serial").is_some(),
+                    order_group: test_order_group(&self.cx, i),
+                    run_after: test_run_after(&self.cx, i),
+                    kind: test_kind(&self.cx, i),
+                    bench_unit: bench_unit(&self.cx, i),
+                    fixture: fixture,
+                    is_method: is_method,
+                    called_as: None,
+                    module_file: self.current_module().file.clone(),
+                    invalid_signature: false,
+                };
+                self.cx.testfns.push(test);
+                true
+            }
+        }
+    }
+
+    // With #![test_harness(default_ignore = "prefix")], tests whose path
+    // starts with the prefix are ignored unless marked #[test(run)].
+    fn is_default_ignored(&self, i: Gc<ast::Item>, path: &[ast::Ident]) -> bool {
+        match self.cx.harness.default_ignore {
+            Some(ref prefix) => {
+                ast_util::path_name_i(path).as_slice().starts_with(prefix.get()) &&
+                    test_meta_item(i, "run").is_none()
+            }
+            None => false
+        }
+    }
+
+    // With -Z keep-invalid-tests, a test or bench function whose signature
+    // was rejected is still listed, as a test which is always ignored.
+    fn collect_invalid_test(&mut self, i: Gc<ast::Item>, path: Vec<ast::Ident>) {
+        if !self.cx.sess.debugging_opt(KEEP_INVALID_TESTS) {
+            return;
+        }
+        let attrs = i.attrs.as_slice();
+        if !attr::contains_name(attrs, "test") && !attr::contains_name(attrs, "bench") {
+            return;
+        }
+        match i.node {
+            ast::ItemFn(..) => {}
+            _ => return
+        }
+        let test = Test {
+            span: i.span,
+            path: path,
+            name: test_name(&self.cx, i),
+            bench: false,
+            bench_as_test: false,
+            ignore: true,
+            ignore_reason: Some(InternedString::new("invalid signature")),
+            should_fail: No,
+            returns_value: false,
+            returns_result: false,
+            should_err: false,
+            diverges: false,
+            timeout_ms: None,
+            iterations: None,
+            groups: Vec::new(),
+            description: test_description(i),
+            allow_fail: false,
+            retries: 0,
+            weight: 1,
+            serial: false,
+            order_group: None,
+            run_after: Vec::new(),
+            kind: InternedString::new("unit"),
+            bench_unit: None,
+            fixture: None,
+            is_method: false,
+            called_as: None,
+            module_file: self.current_module().file.clone(),
+            invalid_signature: true,
+        };
+        self.cx.testfns.push(test);
+    }
+
+    // Collects the test and bench functions defined in an inherent impl.
+    // These are reached through the impl's self type, which is reexported
+    // in place of the functions themselves. A type brought in with `use`
+    // can't be reexported from the module, so tests in an impl of one are
+    // copied into free functions, as a trait's tests are, which are
+    // returned to be added to the module.
+    fn collect_impl_tests(&mut self, i: Gc<ast::Item>) -> Vec<Gc<ast::Item>> {
+        let (generics, self_ty, impl_items) = match i.node {
+            ast::ItemImpl(ref generics, None, ref self_ty, ref impl_items) => {
+                (generics, self_ty, impl_items)
+            }
+            _ => return Vec::new()
+        };
+        let self_ident = match self_ty.node {
+            ast::TyPath(ref path, None, _) if !path.global &&
+                                              path.segments.len() == 1 &&
+                                              path.segments[0].types.is_empty() => {
+                Some(path.segments[0].identifier)
+            }
+            _ => None
+        };
+
+        let mut copies = Vec::new();
+        for impl_item in impl_items.iter() {
+            let method = match *impl_item {
+                ast::MethodImplItem(method) => method
+            };
+            let item = method_as_item(&*method);
+            if !attr::contains_name(item.attrs.as_slice(), "test") &&
+               !attr::contains_name(item.attrs.as_slice(), "bench") {
+                continue;
+            }
+
+            match (self_ident, &method.pe_explicit_self().node) {
+                (Some(self_ident), &ast::SelfStatic) if !generics.is_parameterized() => {
+                    // the impl's own (meaningless) ident is last in the path
+                    let mut path = self.cx.path.clone();
+                    path.pop();
+                    path.push(self_ident);
+                    path.push(item.ident);
+                    if !self.collect_test(item, path, true) {
+                        continue;
+                    }
+                    if !self.current_module().item_names.contains(&self_ident.name) {
+                        copies.push(self.copy_test(item));
+                    } else if !self.current_module().tests.contains(&self_ident) {
+                        self.current_module().tests.push(self_ident);
+                    }
+                }
+                (_, &ast::SelfStatic) => {
+                    self.cx.sess.span_err(method.span,
+                                          "tests in impls must be in a non-generic \
+                                           impl of a named type");
+                }
+                _ => {
+                    self.cx.sess.span_err(method.span,
+                                          "methods taking `self` cannot be used as tests");
+                }
+            }
+        }
+        copies
+    }
+
+    // Collects the test and bench functions given as default methods in a
+    // trait, with paths like `Trait::method`. Without a type implementing
+    // the trait they can't be called as methods, so each is copied into a
+    // free function alongside the trait, which is returned to be added to
+    // the module and is what the harness calls.
+    fn collect_trait_tests(&mut self, i: Gc<ast::Item>) -> Vec<Gc<ast::Item>> {
+        let (generics, trait_items) = match i.node {
+            ast::ItemTrait(ref generics, _, _, ref trait_items) => (generics, trait_items),
+            _ => return Vec::new()
+        };
+
+        let mut copies = Vec::new();
+        for trait_item in trait_items.iter() {
+            let method = match *trait_item {
+                ast::ProvidedMethod(method) => method,
+                ast::RequiredMethod(ref m) => {
+                    if attr::contains_name(m.attrs.as_slice(), "test") ||
+                       attr::contains_name(m.attrs.as_slice(), "bench") {
+                        self.cx.sess.span_err(m.span, "tests in traits must have a body");
+                    }
+                    continue;
+                }
+            };
+            let item = method_as_item(&*method);
+            if !attr::contains_name(item.attrs.as_slice(), "test") &&
+               !attr::contains_name(item.attrs.as_slice(), "bench") {
+                continue;
+            }
+
+            match method.pe_explicit_self().node {
+                ast::SelfStatic if !generics.is_parameterized() => {
+                    // the copy is outside the trait, where `Self` and `self`
+                    // mean nothing
+                    let mut uses_self = SelfUseVisitor { span: None };
+                    visit::walk_item(&mut uses_self, &*item, ());
+                    match uses_self.span {
+                        Some(span) => {
+                            self.cx.sess.span_err(span,
+                                                  "tests in traits can't use `Self` or \
+                                                   `self`, as they're called outside \
+                                                   the trait");
+                            continue;
+                        }
+                        None => {}
+                    }
+
+                    // the trait's ident is already last in the path
+                    let mut path = self.cx.path.clone();
+                    path.push(item.ident);
+                    if self.collect_test(item, path, false) {
+                        copies.push(self.copy_test(item));
+                    }
+                }
+                ast::SelfStatic => {
+                    self.cx.sess.span_err(method.span,
+                                          "tests in traits must be in a non-generic trait");
+                }
+                _ => {
+                    self.cx.sess.span_err(method.span,
+                                          "methods taking `self` cannot be used as tests");
+                }
+            }
+        }
+        copies
+    }
+
+    // Copies `item`, the test just collected, into a free function, which is
+    // what the harness calls. The copy keeps the attributes that still apply
+    // to it, such as #[allow], but not #[test] or #[bench], as it isn't a
+    // test of its own.
+    fn copy_test(&mut self, item: Gc<ast::Item>) -> Gc<ast::Item> {
+        let copy_ident = token::gensym_ident(token::get_ident(item.ident).get());
+        self.cx.testfns.mut_last().unwrap().called_as = Some(copy_ident);
+        self.current_module().tests.push(copy_ident);
+        box(GC) ast::Item {
+            ident: copy_ident,
+            attrs: item.attrs.iter().filter(|attr| {
+                !attr.check_name("test") && !attr.check_name("bench")
+            }).map(|attr| *attr).collect(),
+            id: ast::DUMMY_NODE_ID,
+            .. (*item).clone()
+        }
+    }
+}
+
+// Finds a use of `Self` or `self` in a test declared in a trait, which
+// can't be copied out of the trait.
+struct SelfUseVisitor {
+    span: Option<Span>,
+}
+
+impl Visitor<()> for SelfUseVisitor {
+    fn visit_ty(&mut self, ty: &ast::Ty, _: ()) {
+        match ty.node {
+            ast::TyPath(ref path, _, _) if path.segments[0].identifier.name ==
+                                           token::special_idents::type_self.name => {
+                self.span = Some(ty.span);
+            }
+            _ => visit::walk_ty(self, ty, ())
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &ast::Expr, _: ()) {
+        match expr.node {
+            ast::ExprPath(ref path) if path.segments[0].identifier.name ==
+                                       token::special_idents::self_.name => {
+                self.span = Some(expr.span);
+            }
+            _ => visit::walk_expr(self, expr, ())
+        }
+    }
+}
+
+// Removes #[main] from `item`, leaving any other attributes, such as
+// #[start].
+fn nomain(item: Gc<ast::Item>) -> Gc<ast::Item> {
+    box(GC) ast::Item {
+        attrs: item.attrs.iter().filter_map(|attr| {
+            if !attr.check_name("main") {
+                Some(*attr)
+            } else {
+                None
+            }
+        }).collect(),
+        .. (*item).clone()
+    }
+}
+
+// Finds #[test] and #[bench] on items inside a function body, which the
+// harness can't name.
+struct NestedTestVisitor<'a> {
+    sess: &'a Session,
+}
+
+impl<'a> Visitor<()> for NestedTestVisitor<'a> {
+    fn visit_item(&mut self, i: &ast::Item, _: ()) {
+        for attr in i.attrs.iter() {
+            if attr.check_name("test") || attr.check_name("bench") {
+                self.sess.span_err(attr.span,
+                                   format!("`#[{}]` functions must be declared at \
+                                            module scope, not inside another \
+                                            function", attr.name()).as_slice());
+            }
+        }
+        visit::walk_item(self, i, ());
+    }
+}
+
+// Views a method as a free function item, so that the predicates for test
+// functions also apply to methods.
+fn method_as_item(m: &ast::Method) -> Gc<ast::Item> {
+    box(GC) ast::Item {
+        ident: m.pe_ident(),
+        attrs: m.attrs.clone(),
+        id: m.id,
+        node: ast::ItemFn(m.pe_fn_decl(), m.pe_fn_style(), m.pe_abi(),
+                          m.pe_generics().clone(), m.pe_body()),
+        vis: m.pe_vis(),
+        span: m.span,
+    }
+}
+
+// #[test] and #[bench] are only meaningful on functions, so point out any
+// on other items rather than silently ignoring them.
+fn warn_misplaced_test_attrs(cx: &TestCtxt, i: Gc<ast::Item>) {
+    for attr in i.attrs.iter() {
+        if attr.check_name("test") || attr.check_name("bench") {
+            cx.sess.span_warn(attr.span,
+                              format!("`#[{}]` has no effect on an item which \
+                                       isn't a function", attr.name()).as_slice());
+        }
+    }
+}
+
+// Reports tests which would be given the same name in the harness, since
+// the test runner has no way of telling them apart.
+fn check_duplicate_tests(cx: &TestCtxt) {
+    let mut seen: HashMap<String, Span> = HashMap::new();
+    for test in cx.testfns.iter() {
+        let name = ast_util::path_name_i(test.path.as_slice());
+        let previous = seen.find(&name).map(|sp| *sp);
+        match previous {
+            Some(sp) => {
+                cx.sess.span_err(test.span,
+                                 format!("duplicate test name `{}`", name).as_slice());
+                cx.sess.span_note(sp, "previous test with this name defined here");
+            }
+            None => {
+                seen.insert(name, test.span);
+            }
+        }
+    }
+}
+
+// Tests whose names differ only by ASCII case work, but are the same to a
+// runner which matches names without regard to case, or writes a file for
+// each test on a case-insensitive filesystem.
+fn check_case_collisions(cx: &TestCtxt) {
+    let mut seen: HashMap<String, (String, Span)> = HashMap::new();
+    for test in cx.testfns.iter() {
+        let name = ast_util::path_name_i(test.path.as_slice());
+        let lower = name.as_slice().to_ascii_lower();
+        let previous = seen.find(&lower).map(|&(ref name, sp)| (name.clone(), sp));
+        match previous {
+            // exact duplicates are reported by check_duplicate_tests
+            Some((ref previous, _)) if *previous == name => {}
+            Some((previous, sp)) => {
+                cx.sess.span_warn(test.span,
+                                  format!("test `{}` differs from test `{}` only by case",
+                                          name, previous).as_slice());
+                cx.sess.span_note(sp, "the other test is defined here");
+            }
+            None => {
+                seen.insert(lower, (name, test.span));
+            }
+        }
+    }
+}
+
+// Tests ordered with #[test(after = "...")] are run in stages, which
+// can't be worked out if the groups run after each other in a cycle.
+fn check_test_order(cx: &TestCtxt) {
+    // the groups each group runs after, with the test asking for it
+    let mut deps: HashMap<String, Vec<(String, Span)>> = HashMap::new();
+    for test in cx.testfns.iter() {
+        let group = match test.order_group {
+            Some(ref group) => group.get().to_string(),
+            None => continue
+        };
+        let after: Vec<(String, Span)> = test.run_after.iter().map(|after| {
+            (after.get().to_string(), test.span)
+        }).collect();
+        if !deps.contains_key(&group) {
+            deps.insert(group.clone(), Vec::new());
+        }
+        deps.get_mut(&group).push_all_move(after);
+    }
+
+    for test in cx.testfns.iter() {
+        for after in test.run_after.iter() {
+            if !deps.contains_key(&after.get().to_string()) {
+                cx.sess.span_warn(test.span,
+                                  format!("no test is in the group `{}` which this test \
+                                           runs after", after).as_slice());
+            }
+        }
+    }
+
+    let mut groups: Vec<&String> = deps.keys().collect();
+    groups.sort();
+    let mut done = HashSet::new();
+    for group in groups.move_iter() {
+        find_order_cycle(cx, &deps, group, &mut Vec::new(), &mut done);
+    }
+}
+
+// Follows the groups `group` runs after, depth first, reporting a cycle
+// when one on `stack` comes up again.
+fn find_order_cycle(cx: &TestCtxt,
+                    deps: &HashMap<String, Vec<(String, Span)>>,
+                    group: &String,
+                    stack: &mut Vec<String>,
+                    done: &mut HashSet<String>) {
+    if done.contains(group) {
+        return;
+    }
+    stack.push(group.clone());
+    match deps.find(group) {
+        Some(afters) => {
+            for &(ref after, sp) in afters.iter() {
+                match stack.iter().position(|g| g == after) {
+                    Some(start) => {
+                        let mut cycle = stack.slice_from(start).to_vec();
+                        cycle.push(after.clone());
+                        cx.sess.span_err(sp, format!("test groups run after each other \
+                                                      in a cycle: `{}`",
+                                                     cycle.as_slice().connect("` after `"))
+                                                 .as_slice());
+                    }
+                    None => find_order_cycle(cx, deps, after, stack, done),
+                }
+            }
+        }
+        None => {}
+    }
+    stack.pop();
+    done.insert(group.clone());
+}
+
+fn mk_reexport_mod(cx: &mut TestCtxt, module: ModuleTests) -> Gc<ast::Item> {
+    let ModuleTests { mut tests, mut tested_submods, fixtures, .. } = module;
+    let mut view_items = Vec::new();
+    let super_ = token::str_to_ident("super");
+
+    // The order of the `use`s doesn't matter, but sorting them keeps the
+    // expanded module the same however the source is ordered.
+    let by_name = |a: &ast::Ident, b: &ast::Ident| {
+        token::get_ident(*a).get().cmp(&token::get_ident(*b).get())
+    };
+    tests.sort_by(|a, b| by_name(a, b));
+    tested_submods.sort_by(|a, b| by_name(a, b));
+
+    // The paths are built from the idents the tests and modules were
+    // collected with, syntax context and all, rather than from their names,
+    // so those made by macros are reached just like the rest.
+    //
+    // Tests generated by macros can share an ident, and importing it twice
+    // would only add a confusing error to the duplicate definition that's
+    // already reported.
+    let mut seen_tests = HashSet::new();
+    view_items.extend(tests.move_iter().filter(|r| seen_tests.insert(r.name)).map(|r| {
+        cx.ext_cx.view_use_simple(DUMMY_SP, ast::Public,
+                                  cx.ext_cx.path(DUMMY_SP, vec![super_, r]))
+    }));
+    let mut seen_submods = HashSet::new();
+    view_items.extend(tested_submods.move_iter().filter(|r| seen_submods.insert(r.name)).map(|r| {
+        let path = cx.ext_cx.path(DUMMY_SP, vec![super_, r, cx.reexport_mod_ident]);
+        cx.ext_cx.view_use_simple_(DUMMY_SP, ast::Public, r, path)
+    }));
+    // pub use $alias = super::$fixture_path;
+    view_items.extend(fixtures.move_iter().map(|(alias, fixture_path)| {
+        let mut segments = vec![super_];
+        segments.extend(fixture_path.move_iter());
+        let path = cx.ext_cx.path(DUMMY_SP, segments);
+        cx.ext_cx.view_use_simple_(DUMMY_SP, ast::Public, alias, path)
+    }));
+
+    let reexport_mod = ast::Mod {
+        inner: DUMMY_SP,
+        view_items: view_items,
+        items: Vec::new(),
+    };
+    box(GC) ast::Item {
+        ident: cx.reexport_mod_ident.clone(),
+        attrs: Vec::new(),
+        id: ast::DUMMY_NODE_ID,
+        node: ast::ItemMod(reexport_mod),
+        vis: reexport_mod_visibility(cx),
+        span: DUMMY_SP,
+    }
+}
+
+// Each reexport module is `pub`, as the reexport module of its parent has to
+// reach into it, and so is everything in it. The crate root's, which only
+// the harness uses, is private with #![test_harness(reexports = "private")],
+// so that it isn't part of the crate's interface.
+fn reexport_mod_visibility(cx: &TestCtxt) -> ast::Visibility {
+    if cx.path.is_empty() && cx.harness.private_reexports {
+        ast::Inherited
+    } else {
+        ast::Public
+    }
+}
+
+// Options for the generated harness, given as #![test_harness(...)] on the
+// crate.
+struct HarnessOptions {
+    // main = "path::to::setup": a function to call with the tests instead of
+    // handing them straight to the test runner
+    main: Option<InternedString>,
+    // module = "name": a name for the generated test module, which is
+    // otherwise gensym'd
+    module: Option<InternedString>,
+    // wrap = "path::to::hook": a `fn(&'static str, fn())` which each test is
+    // run through, given the test's name and the test itself
+    wrap: Option<InternedString>,
+    // extra_tests = "path::to::TESTS": a `&'static [test::TestDescAndFn]`
+    // built elsewhere, such as doctests, to run along with the crate's own
+    extra_tests: Option<InternedString>,
+    // args = "path::to::args": a `fn() -> Vec<String>` giving the arguments
+    // for the test runner, for platforms without `std::os::args()`
+    args: Option<codemap::Spanned<InternedString>>,
+    // reexports = "private" or "public": the visibility of the crate root's
+    // reexport module, public by default
+    private_reexports: bool,
+    // default_ignore = "prefix": tests whose path starts with the prefix are
+    // ignored, unless they're marked #[test(run)]
+    default_ignore: Option<InternedString>,
+    // max_threads = N: the most tests the runner should run at once
+    max_threads: Option<uint>,
+    // tests_static = "NAME": the name of the static holding the tests, which
+    // is TESTS otherwise, for binaries linking in several harnesses
+    tests_static: Option<InternedString>,
+    // default_timeout_ms = N: the timeout of the tests which don't give
+    // their own with #[test(timeout_ms = N)]
+    default_timeout_ms: Option<u64>,
+    // no_main: leave out the harness's main, for a crate embedded in a
+    // binary which has its own, and runs the tests through `describe()`
+    no_main: bool,
+}
+
+fn harness_options(sess: &Session, krate: &ast::Crate) -> HarnessOptions {
+    let mut options = HarnessOptions {
+        main: None,
+        module: None,
+        wrap: None,
+        extra_tests: None,
+        args: None,
+        default_ignore: None,
+        private_reexports: false,
+        default_timeout_ms: None,
+        tests_static: None,
+        max_threads: None,
+        no_main: false,
+    };
+    for attr in krate.attrs.iter().filter(|attr| attr.check_name("test_harness")) {
+        let list = match attr.meta_item_list() {
+            Some(list) => list,
+            None => {
+                sess.span_err(attr.span, "test harness options must be of the \
+                                          form `#![test_harness(...)]`");
+                continue;
+            }
+        };
+        for mi in list.iter() {
+            if mi.check_name("main") {
+                match mi.value_str() {
+                    Some(main) => options.main = Some(main),
+                    None => sess.span_err(mi.span, "`main` must be given a path \
+                                                    to a function, as in \
+                                                    `main = \"setup\"`"),
+                }
+            } else if mi.check_name("wrap") {
+                match mi.value_str() {
+                    Some(wrap) => options.wrap = Some(wrap),
+                    None => sess.span_err(mi.span, "`wrap` must be given a path \
+                                                    to a function, as in \
+                                                    `wrap = \"trace_test\"`"),
+                }
+            } else if mi.check_name("extra_tests") {
+                match mi.value_str() {
+                    Some(extra) => options.extra_tests = Some(extra),
+                    None => sess.span_err(mi.span, "`extra_tests` must be given a path \
+                                                    to a static slice of tests, as in \
+                                                    `extra_tests = \"doctests::TESTS\"`"),
+                }
+            } else if mi.check_name("args") {
+                match mi.value_str() {
+                    Some(args) => options.args = Some(codemap::respan(mi.span, args)),
+                    None => sess.span_err(mi.span, "`args` must be given a path \
+                                                    to a function, as in \
+                                                    `args = \"my_args\"`"),
+                }
+            } else if mi.check_name("default_ignore") {
+                match mi.value_str() {
+                    Some(prefix) => options.default_ignore = Some(prefix),
+                    None => sess.span_err(mi.span, "`default_ignore` must be given a \
+                                                    prefix of test paths, as in \
+                                                    `default_ignore = \"wip_\"`"),
+                }
+            } else if mi.check_name("reexports") {
+                match mi.value_str() {
+                    Some(ref vis) if vis.get() == "private" => options.private_reexports = true,
+                    Some(ref vis) if vis.get() == "public" => options.private_reexports = false,
+                    _ => sess.span_err(mi.span, "`reexports` must be given \
+                                                 `\"private\"` or `\"public\"`"),
+                }
+            } else if mi.check_name("max_threads") {
+                match meta_item_uint(sess, *mi) {
+                    Some(0) => sess.span_err(mi.span, "`max_threads` must be at least 1"),
+                    Some(n) => options.max_threads = Some(n as uint),
+                    None => {}
+                }
+            } else if mi.check_name("tests_static") {
+                match mi.value_str() {
+                    Some(ref name) if is_ident(name.get()) => {
+                        options.tests_static = Some(name.clone())
+                    }
+                    _ => sess.span_err(mi.span, "`tests_static` must be given an \
+                                                 identifier, as in \
+                                                 `tests_static = \"MY_TESTS\"`"),
+                }
+            } else if mi.check_name("default_timeout_ms") {
+                match meta_item_uint(sess, *mi) {
+                    Some(timeout) => options.default_timeout_ms = Some(timeout),
+                    None => {}
+                }
+            } else if mi.check_name("no_main") {
+                match mi.node {
+                    ast::MetaWord(_) => options.no_main = true,
+                    _ => sess.span_err(mi.span, "`no_main` doesn't take a value, \
+                                                 as in `#![test_harness(no_main)]`"),
+                }
+            } else if mi.check_name("module") {
+                match mi.value_str() {
+                    Some(ref module) if !module.get().is_empty() &&
+                                        !module.get().contains("::") => {
+                        options.module = Some(module.clone())
+                    }
+                    _ => sess.span_err(mi.span, "`module` must be given a single \
+                                                 identifier, as in \
+                                                 `module = \"my_tests\"`"),
+                }
+            } else {
+                sess.span_err(mi.span,
+                              format!("unknown test harness option `{}`",
+                                      mi.name()).as_slice());
+            }
+        }
+    }
+    // a `main` is handed TESTS alone, so can't take the extra tests too
+    if options.main.is_some() && options.extra_tests.is_some() {
+        sess.span_err(krate.span, "the test harness options `main` and \
+                                   `extra_tests` can't be used together");
+    }
+    // nor does it get the arguments, it finds its own
+    if options.main.is_some() && options.args.is_some() {
+        sess.span_err(krate.span, "the test harness options `main` and \
+                                   `args` can't be used together");
+    }
+    // nor does it start the runner, which is what's told the number of threads
+    if options.main.is_some() && options.max_threads.is_some() {
+        sess.span_err(krate.span, "the test harness options `main` and \
+                                   `max_threads` can't be used together");
+    }
+    if options.extra_tests.is_some() && options.max_threads.is_some() {
+        sess.span_err(krate.span, "the test harness options `extra_tests` and \
+                                   `max_threads` can't be used together");
+    }
+    // and without a main, there's nothing for the others to change
+    if options.no_main {
+        for &(name, set) in [("main", options.main.is_some()),
+                             ("extra_tests", options.extra_tests.is_some()),
+                             ("args", options.args.is_some()),
+                             ("max_threads", options.max_threads.is_some())].iter() {
+            if set {
+                sess.span_err(krate.span,
+                              format!("the test harness options `no_main` and `{}` \
+                                       can't be used together", name).as_slice());
+            }
+        }
+    }
+    options
+}
+
+// The name of the module in each tested module which reexports its tests,
+// and the reexport modules of its submodules, to the harness. Everything in
+// the harness refers to it through this one ident.
+fn reexport_mod_ident(sess: &Session) -> ast::Ident {
+    if sess.debugging_opt(UNHYGIENIC_TEST_REEXPORTS) {
+        token::str_to_ident("__test_reexports")
+    } else {
+        token::gensym_ident("__test_reexports")
+    }
+}
+
+fn mk_test_ctxt<'a>(sess: &'a Session,
+                    reexport_test_harness_main: Option<InternedString>,
+                    test_runner_crate: Option<InternedString>,
+                    harness: HarnessOptions,
+                    krate: &ast::Crate) -> TestCtxt<'a> {
+    let runner_crate = match test_runner_crate {
+        Some(ref name) => token::str_to_ident(name.get()),
+        None => token::str_to_ident("test"),
+    };
+    let crate_name = attr::find_crate_name(krate.attrs.as_slice());
+    TestCtxt {
+        sess: sess,
+        ext_cx: ExtCtxt::new(&sess.parse_sess, sess.opts.cfg.clone(),
+                             ExpansionConfig {
+                                 deriving_hash_type_parameter: false,
+                                 crate_name: "test".to_string(),
+                             }),
+        path: Vec::new(),
+        testfns: Vec::new(),
+        reexport_mod_ident: reexport_mod_ident(sess),
+        reexport_test_harness_main: reexport_test_harness_main,
+        runner_crate: runner_crate,
+        harness: harness,
+        crate_name: crate_name.clone(),
+        is_test_crate: is_test_crate(&crate_name, runner_crate),
+        has_own_entry: attr::contains_name(krate.attrs.as_slice(), "no_main"),
+        is_no_std: attr::contains_name(krate.attrs.as_slice(), "no_std"),
+        config: krate.config.clone(),
+    }
+}
+
+fn test_expn_info() -> ExpnInfo {
+    ExpnInfo {
+        call_site: DUMMY_SP,
+        callee: NameAndSpan {
+            name: "test".to_string(),
+            format: MacroAttribute,
+            span: None
+        }
+    }
+}
+
+fn generate_test_harness(sess: &Session,
+                         reexport_test_harness_main: Option<InternedString>,
+                         test_runner_crate: Option<InternedString>,
+                         harness: HarnessOptions,
+                         krate: ast::Crate) -> ast::Crate {
+    let krate = strip_excluded_test_kind(sess, krate);
+    let krate = strip_inactive_benches(sess, krate);
+    let mut cx = mk_test_ctxt(sess, reexport_test_harness_main,
+                              test_runner_crate, harness, &krate);
+    cx.ext_cx.bt_push(test_expn_info());
+
+    let mut fold = TestHarnessGenerator {
+        cx: cx,
+        modules: Vec::new(),
+        ignored_modules: Vec::new(),
+    };
+    let res = fold.fold_crate(krate);
+    fold.cx.ext_cx.bt_pop();
+    return res;
+}
+
+// With --bench or --no-bench, the kind of test which won't be in the harness
+// isn't compiled at all, as in a build without --test.
+fn strip_excluded_test_kind(sess: &Session, krate: ast::Crate) -> ast::Crate {
+    match sess.opts.test_harness {
+        AllTests => krate,
+        TestsOnly => {
+            config::strip_items(krate, |attrs| {
+                !attr::contains_name(attrs.as_slice(), "bench") ||
+                bench_as_test(attrs.as_slice())
+            })
+        }
+        BenchesOnly => {
+            config::strip_items(krate, |attrs| {
+                !attr::contains_name(attrs.as_slice(), "test") ||
+                attr::contains_name(attrs.as_slice(), "bench")
+            })
+        }
+    }
+}
+
+// A benchmark marked #[bench(cfg(...))] is stripped when the cfg isn't
+// active, just as if it were marked #[cfg(...)], so it's neither described
+// nor reexported.
+fn strip_inactive_benches(sess: &Session, krate: ast::Crate) -> ast::Crate {
+    let config = krate.config.clone();
+    config::strip_items(krate, |attrs| {
+        attrs.iter()
+             .filter(|attr| attr.check_name("bench"))
+             .filter_map(|attr| attr.meta_item_list())
+             .flat_map(|list| list.iter())
+             .filter(|mi| mi.check_name("cfg"))
+             .all(|mi| {
+                 if mi.meta_item_list().is_none() {
+                     sess.span_err(mi.span, "a benchmark's cfg must be given as \
+                                             `cfg(...)`, as in \
+                                             `bench(cfg(feature = \"perf\"))`");
+                     return true;
+                 }
+                 attr::test_cfg(config.as_slice(), Some(*mi).move_iter())
+             })
+    })
+}
+
+fn strip_test_functions(krate: ast::Crate) -> ast::Crate {
+    // When not compiling with --test we should not compile the
+    // #[test] functions, nor the #[bench] ones unless they're marked
+    // #[bench(keep)], to be called as ordinary functions
+    config::strip_items(krate, |attrs| {
+        !attr::contains_name(attrs.as_slice(), "test") &&
+        (!attr::contains_name(attrs.as_slice(), "bench") || bench_flag(attrs, "keep"))
+    })
+}
+
+// Why a function can't be used as a test, pointing at the offending part
+// of its signature.
+enum BadTestSignature {
+    NotAFunction,
+    HasArguments(Span),
+    MissingFixtureArgument,
+    IsGeneric(Span),
+}
+
+// The shape of a function's signature, as far as it can be told before
+// resolve, measured against the number of arguments it's expected to take.
+// Tests and benchmarks are both checked with this, so that they agree on
+// what's wrong with a signature.
+enum SignatureKind {
+    // more arguments than expected, spanning the extra ones
+    ExtraArgs(Span),
+    // fewer arguments than expected
+    MissingArgs,
+    // the expected arguments, but type parameters too, spanning them
+    Generic(Span),
+    // the expected arguments, returning `()`
+    ReturnsNil,
+    // the expected arguments, returning something else
+    ReturnsValue,
+}
+
+fn classify_test_fn(decl: &ast::FnDecl, generics: &ast::Generics,
+                    expected_args: uint) -> SignatureKind {
+    let inputs = decl.inputs.as_slice();
+    if inputs.len() > expected_args {
+        let first = inputs[expected_args].pat.span;
+        let last = inputs.last().unwrap().ty.span;
+        ExtraArgs(codemap::mk_sp(first.lo, last.hi))
+    } else if inputs.len() < expected_args {
+        MissingArgs
+    } else if generics.is_type_parameterized() {
+        // lifetime parameters are harmless, as the harness doesn't have to
+        // pick anything for them
+        let ty_params = generics.ty_params.as_slice();
+        let (first, last) = (ty_params[0].span, ty_params.last().unwrap().span);
+        Generic(codemap::mk_sp(first.lo, last.hi))
+    } else {
+        match decl.output.node {
+            ast::TyNil => ReturnsNil,
+            _ => ReturnsValue
+        }
+    }
+}
+
+fn is_test_fn(cx: &TestCtxt, i: Gc<ast::Item>) -> bool {
+    let has_test_attr = attr::contains_name(i.attrs.as_slice(), "test");
+
+    // a test with a fixture takes the fixture's value as its one argument,
+    // and may return anything
+    fn check_test_signature(i: Gc<ast::Item>,
+                            has_fixture: bool) -> Option<BadTestSignature> {
+        match i.node {
+            ast::ItemFn(ref decl, _, _, ref generics, _) => {
+                let expected_args = if has_fixture { 1 } else { 0 };
+                match classify_test_fn(&**decl, generics, expected_args) {
+                    ExtraArgs(sp) => Some(HasArguments(sp)),
+                    MissingArgs => Some(MissingFixtureArgument),
+                    Generic(sp) => Some(IsGeneric(sp)),
+                    ReturnsNil | ReturnsValue => None,
+                }
+            }
+            _ => Some(NotAFunction)
+        }
+    }
+
+    if !has_test_attr {
+        return false;
+    }
+
+    let has_fixture = test_meta_item(i, "fixture").is_some();
+    let (span, msg) = match check_test_signature(i, has_fixture) {
+        None => return true,
+        Some(NotAFunction) => {
+            (i.span, "functions used as tests must have signature fn() -> T, \
+                      where T is () or another test::Termination, such as \
+                      Result<(), E>.")
+        }
+        Some(HasArguments(sp)) => {
+            (sp, "functions used as tests can't take arguments; did you mean \
+                  `fn() -> ()`?")
+        }
+        Some(MissingFixtureArgument) => {
+            (i.span, "tests with a fixture must take the fixture's value as \
+                      their one argument, as in `fn(db: &Db) -> ()`")
+        }
+        Some(IsGeneric(sp)) => {
+            (sp, "functions used as tests can't be generic; did you mean \
+                  `fn() -> ()`?")
+        }
+    };
+    report_bad_signature(cx, span, msg);
+    false
+}
+
+fn is_bench_fn(cx: &TestCtxt, i: Gc<ast::Item>) -> bool {
+    let has_bench_attr = attr::contains_name(i.attrs.as_slice(), "bench");
+
+    // Returns the span a malformed signature should be reported at, if the
+    // signature is malformed.
+    fn bad_signature_span(i: Gc<ast::Item>) -> Option<Span> {
+        match i.node {
+            ast::ItemFn(ref decl, _, _, ref generics, _) => {
+                match classify_test_fn(&**decl, generics, 1) {
+                    ReturnsNil => {}
+                    ExtraArgs(_) | MissingArgs | Generic(_) | ReturnsValue => {
+                        return Some(i.span)
+                    }
+                }
+                // NB: inadequate check, but we're running
+                // well before resolve, can't get too deep. All we can
+                // tell is that the argument is a `&mut` of something.
+                let arg = decl.inputs.get(0);
+                match arg.ty.node {
+                    ast::TyRptr(_, ast::MutTy { mutbl: ast::MutMutable, .. }) => None,
+                    _ => Some(arg.ty.span)
+                }
+            }
+          _ => Some(i.span)
+        }
+    }
+
+    if !has_bench_attr {
+        return false;
+    }
+
+    match bad_signature_span(i) {
+        Some(span) => {
+            report_bad_signature(cx, span, "functions used as benches must have \
+                                            signature `fn(&mut Bencher) -> ()`");
+            match bench_signature_suggestion(i) {
+                Some(suggestion) => {
+                    cx.sess.span_note(i.span, format!("try declaring it as `{}`",
+                                                      suggestion).as_slice());
+                }
+                None => {}
+            }
+            false
+        }
+        None => true
+    }
+}
+
+// How a function rejected as a benchmark would be declared, keeping its
+// name and the pattern of its first argument: `fn name(b: &mut Bencher)`
+fn bench_signature_suggestion(i: Gc<ast::Item>) -> Option<String> {
+    match i.node {
+        ast::ItemFn(ref decl, _, _, _, _) => {
+            let arg = match decl.inputs.as_slice().head() {
+                Some(arg) => pprust::pat_to_string(&*arg.pat),
+                None => "b".to_string()
+            };
+            Some(format!("fn {}({}: &mut Bencher)", token::get_ident(i.ident), arg))
+        }
+        _ => None
+    }
+}
+
+// A malformed signature is only a warning with -Z keep-invalid-tests, which
+// keeps the function around as an ignored test.
+fn report_bad_signature(cx: &TestCtxt, sp: Span, msg: &str) {
+    if cx.sess.debugging_opt(KEEP_INVALID_TESTS) {
+        cx.sess.span_warn(sp, msg);
+    } else {
+        cx.sess.span_err(sp, msg);
+    }
+    note_macro_definitions(cx, sp);
+}
+
+// For a test written by a macro, points at the definition of each macro it
+// was expanded from, innermost first.
+fn note_macro_definitions(cx: &TestCtxt, sp: Span) {
+    let mut expn_info = sp.expn_info;
+    loop {
+        let ei = match expn_info {
+            Some(ei) => ei,
+            None => break
+        };
+        match ei.callee.span {
+            Some(def_sp) => {
+                cx.sess.span_note(def_sp,
+                                  format!("the test was generated by the macro \
+                                           `{}!` defined here",
+                                          ei.callee.name).as_slice());
+            }
+            None => {}
+        }
+        expn_info = ei.call_site.expn_info;
+    }
+}
+
+// check test_name = "...", which overrides the name the test is shown
+// with
+fn test_name(cx: &TestCtxt, i: Gc<ast::Item>) -> Option<InternedString> {
+    let attr = match i.attrs.iter().find(|attr| attr.check_name("test_name")) {
+        Some(attr) => attr,
+        None => return None,
+    };
+    match attr.value_str() {
+        Some(ref name) if name.get().is_empty() => {
+            cx.sess.span_warn(attr.span, "empty test name, the test's path \
+                                          will be used instead");
+            None
+        }
+        Some(name) => Some(name),
+        None => {
+            cx.sess.span_err(attr.span, "test name must be of the form \
+                                         `#[test_name = \"...\"]`");
+            None
+        }
+    }
+}
+
+// Finds the meta item with the given name in the item's `#[test(...)]`
+// attribute, e.g. `timeout_ms = 100`.
+fn test_meta_item(i: Gc<ast::Item>, name: &str) -> Option<Gc<ast::MetaItem>> {
+    i.attrs.iter()
+           .filter(|attr| attr.check_name("test"))
+           .filter_map(|attr| attr.meta_item_list())
+           .flat_map(|list| list.iter())
+           .find(|mi| mi.check_name(name))
+           .map(|mi| *mi)
+}
+
+// The first line of the function's doc comment, which describes the test.
+fn test_description(i: Gc<ast::Item>) -> Option<InternedString> {
+    for attr in i.attrs.iter().filter(|attr| attr.check_name("doc")) {
+        let doc = match attr.desugar_doc().value_str() {
+            Some(doc) => doc,
+            None => continue
+        };
+        match doc.get().lines().map(|line| line.trim()).find(|line| !line.is_empty()) {
+            Some(line) => return Some(token::intern_and_get_ident(line)),
+            None => {}
+        }
+    }
+    None
+}
+
+// collect every test_group = "...", which put the test in categories
+fn test_groups(cx: &TestCtxt, i: Gc<ast::Item>) -> Vec<InternedString> {
+    i.attrs.iter().filter(|attr| attr.check_name("test_group")).filter_map(|attr| {
+        let group = attr.value_str();
+        if group.is_none() {
+            cx.sess.span_err(attr.span, "test group must be of the form \
+                                         `#[test_group = \"...\"]`");
+        }
+        group
+    }).collect()
+}
 
-        // Remove any #[main] from the AST so it doesn't clash with
-        // the one we're going to add. Only if compiling an executable.
+// The kinds of test #[test(kind = "...")] is expected to give. Any other is
+// warned about, but kept, so that --test-kind can still pick it.
+static KNOWN_TEST_KINDS: &'static [&'static str] = &["unit", "integration"];
 
-        fn nomain(item: Gc<ast::Item>) -> Gc<ast::Item> {
-            box(GC) ast::Item {
-                attrs: item.attrs.iter().filter_map(|attr| {
-                    if !attr.check_name("main") {
-                        Some(*attr)
-                    } else {
-                        None
-                    }
-                }).collect(),
-                .. (*item).clone()
+// check test(kind = "..."), which is "unit" unless given
+fn test_kind(cx: &TestCtxt, i: Gc<ast::Item>) -> InternedString {
+    let mi = match test_meta_item(i, "kind") {
+        Some(mi) => mi,
+        None => return InternedString::new("unit"),
+    };
+    match mi.value_str() {
+        Some(kind) => {
+            if !KNOWN_TEST_KINDS.iter().any(|known| *known == kind.get()) {
+                cx.sess.span_warn(mi.span,
+                                  format!("unknown test kind `{}`; the kinds of test are \
+                                           `unit` and `integration`",
+                                          kind.get()).as_slice());
             }
+            kind
         }
-
-        for i in mod_folded.items.mut_iter() {
-            *i = nomain(*i);
+        None => {
+            cx.sess.span_err(mi.span, "a test's kind must be given as `kind = \"...\"`");
+            InternedString::new("unit")
         }
-        if !tests.is_empty() || !tested_submods.is_empty() {
-            mod_folded.items.push(mk_reexport_mod(&mut self.cx, tests,
-                                                  tested_submods));
-            if !self.cx.path.is_empty() {
-                self.tested_submods.push(self.cx.path[self.cx.path.len()-1]);
-            }
+    }
+}
+
+// check test(group = "..."), which names a group of tests that others can
+// be ordered after
+fn test_order_group(cx: &TestCtxt, i: Gc<ast::Item>) -> Option<InternedString> {
+    test_meta_item(i, "group").and_then(|mi| {
+        let group = mi.value_str();
+        if group.is_none() {
+            cx.sess.span_err(mi.span, "a test's ordering group must be of the form \
+                                       `group = \"...\"`");
         }
+        group
+    })
+}
 
-        mod_folded
-    }
+// collect every test(after = "..."), naming the groups of tests which have
+// to finish before the test starts
+fn test_run_after(cx: &TestCtxt, i: Gc<ast::Item>) -> Vec<InternedString> {
+    i.attrs.iter()
+           .filter(|attr| attr.check_name("test"))
+           .filter_map(|attr| attr.meta_item_list())
+           .flat_map(|list| list.iter())
+           .filter(|mi| mi.check_name("after"))
+           .filter_map(|mi| {
+               let group = mi.value_str();
+               if group.is_none() {
+                   cx.sess.span_err(mi.span, "the group a test runs after must be \
+                                              given as `after = \"...\"`");
+               }
+               group
+           }).collect()
 }
 
-fn mk_reexport_mod(cx: &mut TestCtxt, tests: Vec<ast::Ident>,
-                   tested_submods: Vec<ast::Ident>) -> Gc<ast::Item> {
-    let mut view_items = Vec::new();
-    let super_ = token::str_to_ident("super");
+// check bench(iterations = N), which times a fixed number of iterations
+// instead of letting the runner pick one
+fn bench_iterations(cx: &TestCtxt, i: Gc<ast::Item>) -> Option<uint> {
+    i.attrs.iter()
+           .filter(|attr| attr.check_name("bench"))
+           .filter_map(|attr| attr.meta_item_list())
+           .flat_map(|list| list.iter())
+           .find(|mi| mi.check_name("iterations"))
+           .and_then(|mi| meta_item_uint(cx.sess, *mi))
+           .map(|n| n as uint)
+}
 
-    view_items.extend(tests.move_iter().map(|r| {
-        cx.ext_cx.view_use_simple(DUMMY_SP, ast::Public,
-                                  cx.ext_cx.path(DUMMY_SP, vec![super_, r]))
-    }));
-    view_items.extend(tested_submods.move_iter().map(|r| {
-        let path = cx.ext_cx.path(DUMMY_SP, vec![super_, r, cx.reexport_mod_ident]);
-        cx.ext_cx.view_use_simple_(DUMMY_SP, ast::Public, r, path)
-    }));
+// check bench(unit = "..."), which labels the benchmark's throughput with
+// something other than MB/s
+fn bench_unit(cx: &TestCtxt, i: Gc<ast::Item>) -> Option<InternedString> {
+    i.attrs.iter()
+           .filter(|attr| attr.check_name("bench"))
+           .filter_map(|attr| attr.meta_item_list())
+           .flat_map(|list| list.iter())
+           .find(|mi| mi.check_name("unit"))
+           .and_then(|mi| {
+               let unit = mi.value_str();
+               if unit.is_none() {
+                   cx.sess.span_err(mi.span, "a benchmark's unit must be given as a string, \
+                                              as in `unit = \"MiB/s\"`");
+               }
+               unit
+           })
+}
 
-    let reexport_mod = ast::Mod {
-        inner: DUMMY_SP,
-        view_items: view_items,
-        items: Vec::new(),
-    };
-    box(GC) ast::Item {
-        ident: cx.reexport_mod_ident.clone(),
-        attrs: Vec::new(),
-        id: ast::DUMMY_NODE_ID,
-        node: ast::ItemMod(reexport_mod),
-        vis: ast::Public,
-        span: DUMMY_SP,
-    }
+// check for a flag given as bench(name)
+fn bench_flag(attrs: &[ast::Attribute], name: &str) -> bool {
+    attrs.iter()
+         .filter(|attr| attr.check_name("bench"))
+         .filter_map(|attr| attr.meta_item_list())
+         .flat_map(|list| list.iter())
+         .any(|mi| mi.check_name(name))
 }
 
-fn generate_test_harness(sess: &Session,
-                         reexport_test_harness_main: Option<InternedString>,
-                         krate: ast::Crate) -> ast::Crate {
-    let mut cx: TestCtxt = TestCtxt {
-        sess: sess,
-        ext_cx: ExtCtxt::new(&sess.parse_sess, sess.opts.cfg.clone(),
-                             ExpansionConfig {
-                                 deriving_hash_type_parameter: false,
-                                 crate_name: "test".to_string(),
-                             }),
-        path: Vec::new(),
-        testfns: Vec::new(),
-        reexport_mod_ident: token::gensym_ident("__test_reexports"),
-        reexport_test_harness_main: reexport_test_harness_main,
-        is_test_crate: is_test_crate(&krate),
-        config: krate.config.clone(),
-    };
+// check bench(as_test), which runs the benchmark once, as a test
+fn bench_as_test(attrs: &[ast::Attribute]) -> bool {
+    bench_flag(attrs, "as_test")
+}
 
-    cx.ext_cx.bt_push(ExpnInfo {
-        call_site: DUMMY_SP,
-        callee: NameAndSpan {
-            name: "test".to_string(),
-            format: MacroAttribute,
-            span: None
-        }
-    });
+// Reads the integer value of a `name = N` meta item, reporting an error
+// if the value isn't a non-negative integer literal.
+fn meta_item_uint(sess: &Session, mi: Gc<ast::MetaItem>) -> Option<u64> {
+    match mi.node {
+        ast::MetaNameValue(_, ref lit) => match lit.node {
+            ast::LitInt(n, ast::UnsignedIntLit(_)) |
+            ast::LitInt(n, ast::SignedIntLit(_, ast::Plus)) |
+            ast::LitInt(n, ast::UnsuffixedIntLit(ast::Plus)) => return Some(n),
+            _ => {}
+        },
+        _ => {}
+    }
+    sess.span_err(mi.span,
+                  format!("`{}` must be given an integer value, as in `{} = 10`",
+                          mi.name(), mi.name()).as_slice());
+    None
+}
 
-    let mut fold = TestHarnessGenerator {
-        cx: cx,
-        tests: Vec::new(),
-        tested_submods: Vec::new(),
+// check test(timeout_ms = N), falling back to the crate's
+// #![test_harness(default_timeout_ms = N)]
+fn test_timeout(cx: &TestCtxt, i: Gc<ast::Item>) -> Option<u64> {
+    test_meta_item(i, "timeout_ms").and_then(|mi| meta_item_uint(cx.sess, mi))
+                                   .or(cx.harness.default_timeout_ms)
+}
+
+// check test(fixture = "path::to::setup"), returning the path to the
+// function which makes the value the test is given
+fn test_fixture(cx: &TestCtxt, i: Gc<ast::Item>) -> Option<Vec<ast::Ident>> {
+    let mi = match test_meta_item(i, "fixture") {
+        Some(mi) => mi,
+        None => return None,
     };
-    let res = fold.fold_crate(krate);
-    fold.cx.ext_cx.bt_pop();
-    return res;
+    match mi.value_str() {
+        Some(path) => Some(path.get().split_str("::").map(|segment| {
+            token::str_to_ident(segment)
+        }).collect()),
+        None => {
+            cx.sess.span_err(mi.span, "`fixture` must be given a path to a \
+                                       function, as in `fixture = \"setup\"`");
+            None
+        }
+    }
 }
 
-fn strip_test_functions(krate: ast::Crate) -> ast::Crate {
-    // When not compiling with --test we should not compile the
-    // #[test] functions
-    config::strip_items(krate, |attrs| {
-        !attr::contains_name(attrs.as_slice(), "test") &&
-        !attr::contains_name(attrs.as_slice(), "bench")
-    })
+// check test(retries = N), where no retries means the test is run once
+fn test_retries(cx: &TestCtxt, i: Gc<ast::Item>) -> uint {
+    test_meta_item(i, "retries").and_then(|mi| meta_item_uint(cx.sess, mi))
+                                .map_or(0, |n| n as uint)
 }
 
-fn is_test_fn(cx: &TestCtxt, i: Gc<ast::Item>) -> bool {
-    let has_test_attr = attr::contains_name(i.attrs.as_slice(), "test");
+// check test(weight = N), a relative cost for splitting the tests into
+// evenly sized shards, which is 1 if not given
+fn test_weight(cx: &TestCtxt, i: Gc<ast::Item>) -> uint {
+    test_meta_item(i, "weight").and_then(|mi| meta_item_uint(cx.sess, mi))
+                               .map_or(1, |n| n as uint)
+}
 
-    fn has_test_signature(i: Gc<ast::Item>) -> bool {
-        match &i.node {
-          &ast::ItemFn(ref decl, _, _, ref generics, _) => {
-            let no_output = match decl.output.node {
-                ast::TyNil => true,
-                _ => false
-            };
-            decl.inputs.is_empty()
-                && no_output
-                && !generics.is_parameterized()
-          }
-          _ => false
+// A test marked #[cfg(test)] as well is only built with --test either way,
+// and the cfg has already been checked by the time the test is collected,
+// so point out that it can go.
+fn note_redundant_cfg_test(cx: &TestCtxt, i: Gc<ast::Item>) {
+    if !attr::contains_name(i.attrs.as_slice(), "test") {
+        return;
+    }
+    for attr in i.attrs.iter().filter(|attr| attr.check_name("cfg")) {
+        match attr.meta_item_list() {
+            Some(list) if list.len() == 1 && list[0].check_name("test") &&
+                          list[0].meta_item_list().is_none() &&
+                          list[0].value_str().is_none() => {
+                cx.sess.span_note(attr.span, "`#[cfg(test)]` is redundant on a `#[test]` \
+                                              function, which is only built with --test, \
+                                              and can be removed");
+            }
+            _ => {}
         }
     }
+}
 
-    if has_test_attr && !has_test_signature(i) {
-        let sess = cx.sess;
-        sess.span_err(
-            i.span,
-            "functions used as tests must have signature fn() -> ()."
-        );
+// Checks #[should_err], which expects a test returning a `Result` to return
+// an `Err`. A #[should_fail] test only passes by failing, so one returning a
+// `Result` is warned that an `Err` won't do.
+fn should_err(cx: &TestCtxt, i: Gc<ast::Item>, should_fail: &ShouldFail,
+              returns_result: bool) -> bool {
+    let attr = i.attrs.iter().find(|attr| attr.check_name("should_err"));
+    match (attr, *should_fail != No, returns_result) {
+        (Some(attr), true, _) => {
+            cx.sess.span_err(attr.span, "`#[should_err]` and `#[should_fail]` cannot \
+                                         both be used on one test");
+            false
+        }
+        (Some(attr), false, false) => {
+            cx.sess.span_err(attr.span, "`#[should_err]` can only be used on a test \
+                                         returning a `Result`");
+            false
+        }
+        (Some(_), false, true) => true,
+        (None, true, true) => {
+            cx.sess.span_warn(i.span, "this test is expected to fail, so returning an \
+                                       `Err` from it counts against it; use \
+                                       `#[should_err]` to expect an `Err` instead");
+            false
+        }
+        (None, _, _) => false
     }
-
-    return has_test_attr && has_test_signature(i);
 }
 
-fn is_bench_fn(cx: &TestCtxt, i: Gc<ast::Item>) -> bool {
-    let has_bench_attr = attr::contains_name(i.attrs.as_slice(), "bench");
-
-    fn has_test_signature(i: Gc<ast::Item>) -> bool {
-        match i.node {
-            ast::ItemFn(ref decl, _, _, ref generics, _) => {
-                let input_cnt = decl.inputs.len();
-                let no_output = match decl.output.node {
-                    ast::TyNil => true,
-                    _ => false
-                };
-                let tparm_cnt = generics.ty_params.len();
-                // NB: inadequate check, but we're running
-                // well before resolve, can't get too deep.
-                input_cnt == 1u
-                    && no_output && tparm_cnt == 0u
+// Whether a function is declared to return a `Result`. This runs well before
+// resolve, so it goes by the name of the type alone.
+fn returns_result(i: Gc<ast::Item>) -> bool {
+    match i.node {
+        ast::ItemFn(ref decl, _, _, _, _) => match decl.output.node {
+            ast::TyPath(ref path, _, _) => {
+                path.segments.last().map_or(false, |segment| {
+                    token::get_ident(segment.identifier).get() == "Result"
+                })
             }
-          _ => false
-        }
+            _ => false
+        },
+        _ => false
     }
+}
 
-    if has_bench_attr && !has_test_signature(i) {
-        let sess = cx.sess;
-        sess.span_err(i.span, "functions used as benches must have signature \
-                      `fn(&mut Bencher) -> ()`");
+// Whether a function returns something other than `()`, such as a `Result`
+// or an `Option<()>`, which is handed to `test::assert_test_result` to say
+// whether the test passed, through `Termination::report`. A test returning
+// `()` is called directly. This runs well before resolve, so any type is
+// accepted here, and typeck reports one that isn't a `test::Termination`.
+fn returns_value(i: Gc<ast::Item>) -> bool {
+    match i.node {
+        ast::ItemFn(ref decl, _, _, _, _) => match decl.output.node {
+            ast::TyNil | ast::TyBot => false,
+            _ => true
+        },
+        _ => false
     }
+}
 
-    return has_bench_attr && has_test_signature(i);
+fn diverges(i: Gc<ast::Item>) -> bool {
+    match i.node {
+        ast::ItemFn(ref decl, _, _, _, _) => decl.cf == ast::NoReturn,
+        _ => false
+    }
 }
 
-fn is_ignored(cx: &TestCtxt, i: Gc<ast::Item>) -> bool {
-    i.attrs.iter().any(|attr| {
-        // check ignore(cfg(foo, bar))
-        attr.check_name("ignore") && match attr.meta_item_list() {
+// Returns whether the item is ignored, along with the reason given by an
+// `#[ignore = "..."]` attribute, if any.
+fn is_ignored(cx: &TestCtxt, i: Gc<ast::Item>) -> (bool, Option<InternedString>) {
+    let ignore_attrs: Vec<&ast::Attribute> = i.attrs.iter().filter(|attr| {
+        attr.check_name("ignore")
+    }).collect();
+
+    // the reason is given as ignore = "...", or alongside a cfg as
+    // ignore(cfg(foo), reason = "...")
+    let reason = ignore_attrs.iter().filter_map(|attr| {
+        match attr.meta_item_list() {
+            Some(list) => list.iter().find(|mi| mi.check_name("reason")).and_then(|mi| {
+                let reason = mi.value_str();
+                if reason.is_none() {
+                    cx.sess.span_err(mi.span, "the reason a test is ignored must be \
+                                               given as `reason = \"...\"`");
+                }
+                reason
+            }),
+            None => attr.value_str()
+        }
+    }).next();
+    let has_cfg = ignore_attrs.iter().any(|attr| {
+        attr.meta_item_list().map_or(false, |list| list.iter().any(|mi| mi.check_name("cfg")))
+    });
+
+    let ignore = ignore_attrs.iter().any(|attr| {
+        match attr.meta_item_list() {
+            // check ignore(cfg(foo, bar)), where a list without a cfg, such
+            // as ignore(reason = "..."), ignores unconditionally
             Some(ref cfgs) => {
                 attr::test_cfg(cx.config.as_slice(), cfgs.iter().map(|x| *x))
             }
-            None => true
+            // a reason alone ignores unconditionally, but defers to the
+            // cfg evaluation when one is also given
+            None => attr.value_str().is_none() || !has_cfg
         }
-    })
+    });
+    (ignore || ignore_if(cx, i), reason)
+}
+
+// check test(ignore_if(foo, bar)), which ignores the test when the given
+// cfg is active, exactly as cfg(foo, bar) would include an item
+fn ignore_if(cx: &TestCtxt, i: Gc<ast::Item>) -> bool {
+    let mi = match test_meta_item(i, "ignore_if") {
+        Some(mi) => mi,
+        None => return false,
+    };
+    match mi.meta_item_list() {
+        Some(cfgs) => {
+            let cfg = attr::mk_list_item(InternedString::new("cfg"),
+                                         cfgs.to_vec());
+            attr::test_cfg(cx.config.as_slice(), Some(cfg).move_iter())
+        }
+        None => {
+            cx.sess.span_err(mi.span, "`ignore_if` must be given a cfg, as in \
+                                       `ignore_if(windows)`");
+            false
+        }
+    }
+}
+
+fn should_fail(cx: &TestCtxt, i: Gc<ast::Item>) -> ShouldFail {
+    let sess = cx.sess;
+    // #[should_panic] is accepted as a synonym for #[should_fail]
+    let fail_attr = i.attrs.iter().find(|attr| attr.check_name("should_fail"));
+    let panic_attr = i.attrs.iter().find(|attr| attr.check_name("should_panic"));
+    let attr = match (fail_attr, panic_attr) {
+        (Some(_), Some(attr)) => {
+            sess.span_err(attr.span, "`#[should_fail]` and `#[should_panic]` \
+                                      cannot both be used on one function");
+            attr
+        }
+        (Some(attr), None) | (None, Some(attr)) => attr,
+        (None, None) => return No,
+    };
+
+    // check should_fail(cfg(foo, bar)), which only expects a failure when
+    // the cfg is active
+    match attr.meta_item_list() {
+        Some(list) => {
+            if !attr::test_cfg(cx.config.as_slice(), list.iter().map(|x| *x)) {
+                return No;
+            }
+        }
+        None => {}
+    }
+
+    // check should_fail(expected = "...") or should_panic(expected = "..."),
+    // or expected(any = "...", any = "...") for a choice of messages, as a
+    // meta item can't list bare strings
+    let expected = attr.meta_item_list().and_then(|list| {
+        list.iter().find(|mi| mi.check_name("expected"))
+    });
+    match expected {
+        Some(mi) => match expected_messages(*mi) {
+            Some(msgs) => YesWithMessage(msgs),
+            None => {
+                sess.span_err(attr.span, "expected failure message must be of the \
+                                          form `expected = \"...\"` or \
+                                          `expected(any = \"...\", any = \"...\")`");
+                Yes
+            }
+        },
+        None => Yes
+    }
 }
 
-fn should_fail(i: Gc<ast::Item>) -> bool {
-    attr::contains_name(i.attrs.as_slice(), "should_fail")
+fn expected_messages(mi: Gc<ast::MetaItem>) -> Option<Vec<InternedString>> {
+    match mi.node {
+        ast::MetaNameValue(..) => mi.value_str().map(|msg| vec![msg]),
+        ast::MetaList(_, ref choices) if !choices.is_empty() => {
+            choices.iter().map(|choice| {
+                if choice.check_name("any") { choice.value_str() } else { None }
+            }).collect()
+        }
+        _ => None
+    }
 }
 
 /*
@@ -342,12 +1987,26 @@ mod __test {
   static tests : &'static [test::TestDescAndFn] = &[
     ... the list of tests in the crate ...
   ];
+
+  pub fn describe() -> &'static [test::TestDescAndFn] { tests }
+
+  pub static HARNESS_VERSION: u32 = test::HARNESS_VERSION;
+
+  pub static TEST_COUNT: uint = ...;
+  pub static TEST_ONLY_COUNT: uint = ...;
+  pub static BENCH_COUNT: uint = ...;
+  pub static IGNORED_TESTS: &'static [&'static str] = &[...];
+  pub static SHOULD_FAIL_TESTS: &'static [&'static str] = &[...];
 }
 
+where `test` is replaced by the crate named in #![test_runner_crate = "..."],
+if there is one, and `__test` is gensym'd unless it's named by
+#![test_harness(module = "...")]. #![test_harness(no_main)] leaves out `main`.
+
 */
 
 fn mk_std(cx: &TestCtxt) -> ast::ViewItem {
-    let id_test = token::str_to_ident("test");
+    let id_test = cx.runner_crate;
     let (vi, vis) = if cx.is_test_crate {
         (ast::ViewItemUse(
             box(GC) nospan(ast::ViewPathSimple(id_test,
@@ -367,31 +2026,181 @@ fn mk_std(cx: &TestCtxt) -> ast::ViewItem {
 }
 
 fn mk_test_module(cx: &TestCtxt, reexport_test_harness_main: &Option<InternedString>)
-                  -> (Gc<ast::Item>, Option<ast::ViewItem>) {
+                  -> (Gc<ast::Item>, Option<(Vec<ast::Ident>, ast::ViewItem)>) {
     // Link to test crate
-    let view_items = vec!(mk_std(cx));
+    let mut view_items = vec!(mk_std(cx));
+    // a #![no_std] crate's descriptors name Option through core instead
+    if cx.is_no_std {
+        view_items.push(ast::ViewItem {
+            node: ast::ViewItemExternCrate(token::str_to_ident("core"), None,
+                                           ast::DUMMY_NODE_ID),
+            attrs: Vec::new(),
+            vis: ast::Inherited,
+            span: DUMMY_SP
+        });
+    }
 
-    // A constant vector of test descriptors.
+    // A constant vector of test descriptors, and its length.
     let tests = mk_tests(cx);
 
     // The synthesized main function which will call the console test runner
-    // with our list of tests
-    let mainfn = (quote_item!(&cx.ext_cx,
-        pub fn main() {
-            #![main]
-            use std::slice::Slice;
-            test::test_main_static(::std::os::args().as_slice(), TESTS);
+    // with our list of tests, or the crate's own function given by
+    // #![test_harness(main = "...")]
+    let runner_crate = cx.runner_crate;
+    // ::std::os::args(), or ::$args_path() with #![test_harness(args = "...")]
+    let args = match cx.harness.args {
+        Some(ref args) => {
+            let ecx = &cx.ext_cx;
+            let args_path = args.node.get().split_str("::").map(|s| ecx.ident_of(s)).collect();
+            ecx.expr_call(args.span, ecx.expr_path(ecx.path_global(args.span, args_path)),
+                          vec![])
+        }
+        None => quote_expr!(&cx.ext_cx, ::std::os::args()),
+    };
+    let tests_ident = tests_static_ident(cx);
+    let mainfn = match cx.harness.main {
+        Some(ref setup) => {
+            let ecx = &cx.ext_cx;
+            let setup_path = setup.get().split_str("::").map(|s| ecx.ident_of(s)).collect();
+            // ::$setup_path(TESTS)
+            let call = ecx.expr_call(DUMMY_SP,
+                                     ecx.expr_path(ecx.path_global(DUMMY_SP, setup_path)),
+                                     vec![ecx.expr_ident(DUMMY_SP, tests_ident)]);
+            quote_item!(ecx,
+                pub fn main() {
+                    #![main]
+                    $call;
+                }
+            )
+        }
+        // without std, the runner has to find the arguments itself
+        None if cx.is_no_std => {
+            if cx.harness.extra_tests.is_some() {
+                cx.sess.err("the test harness option `extra_tests` can't be used \
+                             in a `#![no_std]` crate");
+            }
+            if cx.harness.args.is_some() {
+                cx.sess.err("the test harness option `args` can't be used \
+                             in a `#![no_std]` crate");
+            }
+            if cx.harness.max_threads.is_some() {
+                cx.sess.err("the test harness option `max_threads` can't be used \
+                             in a `#![no_std]` crate");
+            }
+            quote_item!(&cx.ext_cx,
+                pub fn main() {
+                    #![main]
+                    $runner_crate::test_main_static_os_args($tests_ident);
+                }
+            )
+        }
+        None if cx.harness.extra_tests.is_some() => {
+            let ecx = &cx.ext_cx;
+            let extra = cx.harness.extra_tests.as_ref().unwrap();
+            let extra_path = extra.get().split_str("::").map(|s| ecx.ident_of(s)).collect();
+            let extra = ecx.expr_path(ecx.path_global(DUMMY_SP, extra_path));
+            quote_item!(ecx,
+                pub fn main() {
+                    #![main]
+                    use std::slice::Slice;
+                    $runner_crate::test_main_static_with_extra($args.as_slice(),
+                                                               $tests_ident, $extra);
+                }
+            )
+        }
+        None => match cx.harness.max_threads {
+            Some(max_threads) => {
+                quote_item!(&cx.ext_cx,
+                    pub fn main() {
+                        #![main]
+                        use std::slice::Slice;
+                        $runner_crate::test_main_static_with_max_threads($args.as_slice(),
+                                                                         $tests_ident,
+                                                                         $max_threads);
+                    }
+                )
+            }
+            None => {
+                quote_item!(&cx.ext_cx,
+                    pub fn main() {
+                        #![main]
+                        use std::slice::Slice;
+                        $runner_crate::test_main_static($args.as_slice(), $tests_ident);
+                    }
+                )
+            }
+        }
+    }.unwrap();
+    // A crate with its own entry point can still run the tests by calling
+    // the harness's main, through #![reexport_test_harness_main].
+    let mainfn = if cx.has_own_entry { nomain(mainfn) } else { mainfn };
+    // With #![test_harness(no_main)] there's no main at all, and the
+    // binary the crate is built into runs the tests itself.
+    let mainfn = if cx.harness.no_main { None } else { Some(mainfn) };
+
+    // A named way to get at the tests, for runners written in Rust.
+    let describe = quote_item!(&cx.ext_cx,
+        pub fn describe() -> &'static [$runner_crate::TestDescAndFn] {
+            $tests_ident
         }
-    )).unwrap();
+    ).unwrap();
+
+    // The version of the descriptors, from the crate they're built for.
+    let harness_version = quote_item!(&cx.ext_cx,
+        pub static HARNESS_VERSION: u32 = $runner_crate::HARNESS_VERSION;
+    ).unwrap();
+
+    let mut items: Vec<Gc<ast::Item>> = mainfn.move_iter().collect();
+    items.push(describe);
+    items.push(harness_version);
+    items.extend(tests.move_iter());
+
+    // With -Z test-registry, an unmangled function which hands out the
+    // tests, for runners which load the crate as a library. The number of
+    // tests is written to `len`.
+    if cx.sess.debugging_opt(TEST_REGISTRY) {
+        let registry = quote_item!(&cx.ext_cx,
+            #[no_mangle]
+            pub extern "C" fn __rust_test_registry(len: *mut uint)
+                                                   -> *const $runner_crate::TestDescAndFn {
+                unsafe { *len = $tests_ident.len(); }
+                $tests_ident.as_ptr()
+            }
+        ).unwrap();
+        items.push(registry);
+    }
+
+    // With -Z test-run-one, a function which runs the test with the given
+    // name, as it appears in TESTS, and says whether it passed, for
+    // binaries which embed the tests and pick which to run themselves.
+    if cx.sess.debugging_opt(TEST_RUN_ONE) {
+        let run_one = quote_item!(&cx.ext_cx,
+            pub fn run_one(name: &str) -> bool {
+                for test in $tests_ident.iter() {
+                    match test.desc.name {
+                        $runner_crate::StaticTestName(test_name) if test_name == name => {
+                            return $runner_crate::run_static_test(test);
+                        }
+                        _ => {}
+                    }
+                }
+                false
+            }
+        ).unwrap();
+        items.push(run_one);
+    }
 
     let testmod = ast::Mod {
         inner: DUMMY_SP,
         view_items: view_items,
-        items: vec!(mainfn, tests),
+        items: items,
     };
     let item_ = ast::ItemMod(testmod);
 
-    let mod_ident = token::gensym_ident("__test");
+    let mod_ident = match cx.harness.module {
+        Some(ref module) => token::str_to_ident(module.get()),
+        None => token::gensym_ident("__test"),
+    };
     let item = ast::Item {
         ident: mod_ident,
         attrs: Vec::new(),
@@ -400,21 +2209,31 @@ fn mk_test_module(cx: &TestCtxt, reexport_test_harness_main: &Option<InternedStr
         vis: ast::Public,
         span: DUMMY_SP,
     };
-    let reexport = reexport_test_harness_main.as_ref().map(|s| {
-        // building `use <ident> = __test::main`
-        let reexport_ident = token::str_to_ident(s.get());
+    if cx.harness.no_main && reexport_test_harness_main.is_some() {
+        cx.sess.err("#![reexport_test_harness_main] can't be used with \
+                     #![test_harness(no_main)], which leaves out the main to reexport");
+    }
+    let reexport = reexport_test_harness_main.as_ref().filtered(|_| {
+        !cx.harness.no_main
+    }).map(|s| {
+        // building `use <ident> = __test::main`, to go in the module named
+        // by any leading segments of the path, as in "harness::run"
+        let mut segments: Vec<ast::Ident> = s.get().split_str("::").map(|segment| {
+            token::str_to_ident(segment)
+        }).collect();
+        let reexport_ident = segments.pop().unwrap();
 
         let use_path =
             nospan(ast::ViewPathSimple(reexport_ident,
                                        path_node(vec![mod_ident, token::str_to_ident("main")]),
                                        ast::DUMMY_NODE_ID));
 
-        ast::ViewItem {
+        (segments, ast::ViewItem {
             node: ast::ViewItemUse(box(GC) use_path),
             attrs: vec![],
             vis: ast::Inherited,
             span: DUMMY_SP
-        }
+        })
     });
 
     debug!("Synthetic test module:\n{}\n", pprust::item_to_string(&item));
@@ -422,6 +2241,30 @@ fn mk_test_module(cx: &TestCtxt, reexport_test_harness_main: &Option<InternedStr
     (box(GC) item, reexport)
 }
 
+// Adds `vi` to the module at `path` within `module`, returning false if
+// there's no such module.
+fn add_view_item(module: &mut ast::Mod, path: &[ast::Ident], vi: ast::ViewItem) -> bool {
+    if path.is_empty() {
+        module.view_items.push(vi);
+        return true;
+    }
+    for item in module.items.mut_iter() {
+        if item.ident.name != path[0].name {
+            continue;
+        }
+        let mut new_item = (**item).clone();
+        let added = match new_item.node {
+            ast::ItemMod(ref mut m) => add_view_item(m, path.slice_from(1), vi.clone()),
+            _ => continue
+        };
+        if added {
+            *item = box(GC) new_item;
+        }
+        return added;
+    }
+    false
+}
+
 fn nospan<T>(t: T) -> codemap::Spanned<T> {
     codemap::Spanned { node: t, span: DUMMY_SP }
 }
@@ -438,109 +2281,438 @@ fn path_node(ids: Vec<ast::Ident> ) -> ast::Path {
     }
 }
 
-fn mk_tests(cx: &TestCtxt) -> Gc<ast::Item> {
+fn mk_tests(cx: &TestCtxt) -> Vec<Gc<ast::Item>> {
     // The vector of test_descs for this crate
     let test_descs = mk_test_descs(cx);
-
-    // FIXME #15962: should be using quote_item, but that stringifies
-    // __test_reexports, causing it to be reinterned, losing the
-    // gensym information.
-    let sp = DUMMY_SP;
     let ecx = &cx.ext_cx;
-    let struct_type = ecx.ty_path(ecx.path(sp, vec![ecx.ident_of("self"),
-                                                    ecx.ident_of("test"),
-                                                    ecx.ident_of("TestDescAndFn")]),
-                                  None);
-    let static_lt = ecx.lifetime(sp, token::special_idents::static_lifetime.name);
-    // &'static [self::test::TestDescAndFn]
-    let static_type = ecx.ty_rptr(sp,
-                                  ecx.ty(sp, ast::TyVec(struct_type)),
-                                  Some(static_lt),
-                                  ast::MutImmutable);
-    // static TESTS: $static_type = &[...];
-    ecx.item_static(sp,
-                    ecx.ident_of("TESTS"),
-                    static_type,
-                    ast::MutImmutable,
-                    test_descs)
-}
-
-fn is_test_crate(krate: &ast::Crate) -> bool {
-    match attr::find_crate_name(krate.attrs.as_slice()) {
-        Some(ref s) if "test" == s.get().as_slice() => true,
+    let runner_crate = cx.runner_crate;
+    let tests_ident = tests_static_ident(cx);
+
+    // TEST_ONLY_COUNT and BENCH_COUNT add up to TEST_COUNT
+    let test_count = cx.testfns.len();
+    let bench_count = cx.testfns.iter().filter(|test| test.bench).count();
+    let test_only_count = test_count - bench_count;
+
+    // the names of the ignored tests, as they appear in TESTS, for runners
+    // which only want to list them
+    let ignored_names = ordered_tests(cx).move_iter().filter(|test| test.ignore).map(|test| {
+        ecx.expr_str(DUMMY_SP, test_run_name(cx, test))
+    }).collect();
+    let ignored_names = ecx.expr_vec_slice(DUMMY_SP, ignored_names);
+
+    // the names of the tests expected to fail, for tools auditing them
+    let should_fail_names = ordered_tests(cx).move_iter().filter(|test| {
+        test.should_fail != No
+    }).map(|test| {
+        ecx.expr_str(DUMMY_SP, test_run_name(cx, test))
+    }).collect();
+    let should_fail_names = ecx.expr_vec_slice(DUMMY_SP, should_fail_names);
+
+    // With -Z sized-test-array the tests are kept in an array whose length is
+    // part of its type, and TESTS is a slice of it
+    let mut items = if cx.sess.debugging_opt(SIZED_TEST_ARRAY) {
+        let array_ident = token::str_to_ident(format!("{}_ARRAY",
+                                                      token::get_ident(tests_ident)).as_slice());
+        vec![quote_item!(ecx,
+                 static $array_ident: [self::$runner_crate::TestDescAndFn, ..$test_count] =
+                     $test_descs;
+             ).unwrap(),
+             quote_item!(ecx,
+                 static $tests_ident: &'static [self::$runner_crate::TestDescAndFn] =
+                     &$array_ident;
+             ).unwrap()]
+    } else {
+        let test_descs = box(GC) ast::Expr {
+            id: ast::DUMMY_NODE_ID,
+            node: ast::ExprVstore(test_descs, ast::ExprVstoreSlice),
+            span: DUMMY_SP,
+        };
+        vec![quote_item!(ecx,
+                 static $tests_ident: &'static [self::$runner_crate::TestDescAndFn] =
+                     $test_descs;
+             ).unwrap()]
+    };
+    items.push_all_move(vec![
+        quote_item!(ecx, pub static TEST_COUNT: uint = $test_count;).unwrap(),
+        quote_item!(ecx, pub static TEST_ONLY_COUNT: uint = $test_only_count;).unwrap(),
+        quote_item!(ecx, pub static BENCH_COUNT: uint = $bench_count;).unwrap(),
+        quote_item!(ecx,
+            pub static IGNORED_TESTS: &'static [&'static str] = $ignored_names;
+        ).unwrap(),
+        quote_item!(ecx,
+            pub static SHOULD_FAIL_TESTS: &'static [&'static str] = $should_fail_names;
+        ).unwrap()]);
+    items
+}
+
+// The static the tests are kept in, TESTS unless it's renamed by
+// #![test_harness(tests_static = "...")]
+fn tests_static_ident(cx: &TestCtxt) -> ast::Ident {
+    match cx.harness.tests_static {
+        Some(ref name) => token::str_to_ident(name.get()),
+        None => token::str_to_ident("TESTS"),
+    }
+}
+
+fn is_test_crate(crate_name: &Option<InternedString>, runner_crate: ast::Ident) -> bool {
+    match *crate_name {
+        Some(ref s) if token::get_ident(runner_crate).get() == s.get() => true,
         _ => false
     }
 }
 
+// The tests in the order they were found in, which is the same every time,
+// or with -Z sort-test-names, sorted by path, so that the order of the
+// harness's lists doesn't depend on how the crate's source is laid out.
+fn ordered_tests<'a>(cx: &'a TestCtxt) -> Vec<&'a Test> {
+    if !cx.sess.debugging_opt(SORT_TEST_NAMES) {
+        return cx.testfns.iter().collect();
+    }
+    // tests which share a path, such as a test generated twice by a macro,
+    // are ordered by where they're defined
+    let mut tests: Vec<(String, u32, &Test)> = cx.testfns.iter().map(|test| {
+        let codemap::BytePos(pos) = codemap::original_sp(test.span, DUMMY_SP).lo;
+        (ast_util::path_name_i(test.path.as_slice()), pos, test)
+    }).collect();
+    tests.sort_by(|&(ref a, a_pos, _), &(ref b, b_pos, _)| {
+        match a.cmp(b) {
+            Equal => a_pos.cmp(&b_pos),
+            order => order
+        }
+    });
+    tests.move_iter().map(|(_, _, test)| test).collect()
+}
+
 fn mk_test_descs(cx: &TestCtxt) -> Gc<ast::Expr> {
     debug!("building test vector from {} tests", cx.testfns.len());
 
+    let tests = ordered_tests(cx);
+
+    // A crate without tests gets an empty vector, whose element type comes
+    // from the type TESTS is declared with, so it needs no special casing
     box(GC) ast::Expr {
         id: ast::DUMMY_NODE_ID,
-        node: ast::ExprVstore(box(GC) ast::Expr {
-            id: ast::DUMMY_NODE_ID,
-            node: ast::ExprVec(cx.testfns.iter().map(|test| {
-                mk_test_desc_and_fn_rec(cx, test)
-            }).collect()),
-            span: DUMMY_SP,
-        }, ast::ExprVstoreSlice),
+        node: ast::ExprVec(tests.move_iter().map(|test| {
+            mk_test_desc_and_fn_rec(cx, test)
+        }).collect()),
         span: DUMMY_SP,
     }
 }
 
-fn mk_test_desc_and_fn_rec(cx: &TestCtxt, test: &Test) -> Gc<ast::Expr> {
-    // FIXME #15962: should be using quote_expr, but that stringifies
-    // __test_reexports, causing it to be reinterned, losing the
-    // gensym information.
+// The name a test is shown with: the path to the #[test] function,
+// "foo::bar::baz", or the name given by #[test_name = "..."]
+fn test_display_name(test: &Test) -> InternedString {
+    match test.name {
+        Some(ref name) => name.clone(),
+        None => {
+            let path_string = ast_util::path_name_i(test.path.as_slice());
+            token::intern_and_get_ident(path_string.as_slice())
+        }
+    }
+}
+
+// The crate's name, for -Z qualify-test-names
+fn crate_name(cx: &TestCtxt) -> Option<InternedString> {
+    cx.crate_name.clone().or_else(|| {
+        cx.sess.opts.crate_name.as_ref().map(|name| token::intern_and_get_ident(name.as_slice()))
+    })
+}
+
+// The name the test is run under: the display name, qualified with the
+// crate's name under -Z qualify-test-names, "mycrate::foo::bar", so that the
+// results of several test crates can be told apart.
+fn test_run_name(cx: &TestCtxt, test: &Test) -> InternedString {
+    let name = test_display_name(test);
+    if !cx.sess.debugging_opt(QUALIFY_TEST_NAMES) {
+        return name;
+    }
+    match crate_name(cx) {
+        Some(crate_name) => {
+            token::intern_and_get_ident(format!("{}::{}", crate_name, name).as_slice())
+        }
+        None => name
+    }
+}
+
+// Where the test is defined; a test produced by a macro is reported at the
+// macro's invocation.
+fn test_location(cx: &TestCtxt, test: &Test) -> codemap::Loc {
+    cx.sess.codemap().lookup_char_pos(codemap::original_sp(test.span, DUMMY_SP).lo)
+}
+
+// The file `m`'s items were parsed from: the file a module declared with
+// #[path = "..."] or `mod name;` was loaded from, and the enclosing file for
+// an inline module.
+fn module_file(cx: &TestCtxt, m: &ast::Mod) -> Option<String> {
+    let span = codemap::original_sp(m.inner, DUMMY_SP);
+    if span == DUMMY_SP {
+        return None;
+    }
+    Some(cx.sess.codemap().lookup_char_pos(span.lo).file.name.clone())
+}
+
+// Lists the ignored tests, as a note with -Z list-ignored-tests and in the
+// debug log otherwise.
+fn note_ignored_tests(cx: &TestCtxt) {
+    let ignored: Vec<&Test> = cx.testfns.iter().filter(|test| test.ignore).collect();
+    debug!("{} of {} tests are ignored", ignored.len(), cx.testfns.len());
+    let note = cx.sess.debugging_opt(LIST_IGNORED_TESTS);
+    if note {
+        cx.sess.note(format!("{} of {} tests are ignored",
+                             ignored.len(), cx.testfns.len()).as_slice());
+    }
+    for test in ignored.iter() {
+        let name = test_display_name(*test);
+        debug!("ignored test {}", name);
+        if note {
+            cx.sess.span_note(test.span,
+                              format!("ignored test `{}`", name).as_slice());
+        }
+    }
+}
+
+#[deriving(Encodable)]
+struct ManifestEntry {
+    name: String,
+    ignore: bool,
+    bench: bool,
+    should_fail: bool,
+    // the messages one of which the failure has to contain, if the test
+    // expects particular ones; null otherwise
+    expected_messages: Option<Vec<String>>,
+    file: String,
+    line: uint,
+    // the file the test's module was loaded from, null for a module the
+    // compiler made up
+    module_file: Option<String>,
+}
+
+// Writes the tests going into the harness to `path` as a JSON list, for
+// --test-manifest.
+fn write_test_manifest(cx: &TestCtxt, path: &Path) {
+    let entries: Vec<ManifestEntry> = cx.testfns.iter().map(|test| {
+        let loc = test_location(cx, test);
+        ManifestEntry {
+            name: test_display_name(test).get().to_string(),
+            ignore: test.ignore,
+            bench: test.bench,
+            should_fail: test.should_fail != No,
+            expected_messages: match test.should_fail {
+                YesWithMessage(ref msgs) => {
+                    Some(msgs.iter().map(|msg| msg.get().to_string()).collect())
+                }
+                No | Yes => None,
+            },
+            file: loc.file.name.clone(),
+            line: loc.line,
+            module_file: test.module_file.clone(),
+        }
+    }).collect();
+
+    let result = File::create(path).and_then(|mut file| {
+        let mut encoder = json::Encoder::new(&mut file);
+        entries.encode(&mut encoder)
+    });
+    match result {
+        Ok(()) => {}
+        Err(e) => {
+            cx.sess.err(format!("could not write test manifest `{}`: {}",
+                                path.display(), e).as_slice());
+        }
+    }
+}
 
+fn mk_test_desc_and_fn_rec(cx: &TestCtxt, test: &Test) -> Gc<ast::Expr> {
     let span = test.span;
     let path = test.path.clone();
     let ecx = &cx.ext_cx;
     let self_id = ecx.ident_of("self");
-    let test_id = ecx.ident_of("test");
+    let test_id = cx.runner_crate;
 
     // creates self::test::$name
     let test_path = |name| {
         ecx.path(span, vec![self_id, test_id, ecx.ident_of(name)])
     };
-    // creates $name: $expr
-    let field = |name, expr| ecx.field_imm(span, ecx.ident_of(name), expr);
+    // creates ::std::option::Some($expr), or self::core::option::Some($expr)
+    // in a #![no_std] crate, and likewise for None
+    let option_path = |name| {
+        ecx.path(span, vec![self_id, ecx.ident_of("core"), ecx.ident_of("option"),
+                            ecx.ident_of(name)])
+    };
+    let some = |expr| {
+        if cx.is_no_std {
+            ecx.expr_call(span, ecx.expr_path(option_path("Some")), vec![expr])
+        } else {
+            ecx.expr_some(span, expr)
+        }
+    };
+    let none = || {
+        if cx.is_no_std { ecx.expr_path(option_path("None")) } else { ecx.expr_none(span) }
+    };
 
     debug!("encoding {}", ast_util::path_name_i(path.as_slice()));
 
-    // path to the #[test] function: "foo::bar::baz"
-    let path_string = ast_util::path_name_i(path.as_slice());
-    let name_expr = ecx.expr_str(span, token::intern_and_get_ident(path_string.as_slice()));
-
-    // self::test::StaticTestName($name_expr)
-    let name_expr = ecx.expr_call(span,
-                                  ecx.expr_path(test_path("StaticTestName")),
-                                  vec![name_expr]);
-
-    let ignore_expr = ecx.expr_bool(span, test.ignore);
-    let fail_expr = ecx.expr_bool(span, test.should_fail);
-
-    // self::test::TestDesc { ... }
-    let desc_expr = ecx.expr_struct(
-        span,
-        test_path("TestDesc"),
-        vec![field("name", name_expr),
-             field("ignore", ignore_expr),
-             field("should_fail", fail_expr)]);
+    let name_expr = ecx.expr_str(span, test_run_name(cx, test));
+    let ignore_reason_expr = match test.ignore_reason {
+        Some(ref reason) => some(ecx.expr_str(span, reason.clone())),
+        None => none()
+    };
+    let timeout_expr = match test.timeout_ms {
+        Some(ms) => {
+            let ms_expr = ecx.expr_lit(span, ast::LitInt(ms, ast::UnsignedIntLit(ast::TyU64)));
+            some(ms_expr)
+        }
+        None => none()
+    };
+    let iterations_expr = match test.iterations {
+        Some(n) => some(ecx.expr_uint(span, n)),
+        None => none()
+    };
+    let groups_expr = ecx.expr_vec_slice(span, test.groups.iter().map(|group| {
+        ecx.expr_str(span, group.clone())
+    }).collect());
+    let description_expr = match test.description {
+        Some(ref description) => some(ecx.expr_str(span, description.clone())),
+        None => none()
+    };
+    // the module the test is in, e.g. "a::b" for a::b::test, so runners can
+    // show the tests as a tree
+    let module_path = ast_util::path_name_i(path.slice_to(path.len() - 1));
+    let module_path_expr = ecx.expr_str(span,
+                                        token::intern_and_get_ident(module_path.as_slice()));
+    let loc = test_location(cx, test);
+    let file_expr = ecx.expr_str(span, token::intern_and_get_ident(loc.file.name.as_slice()));
+    let line_expr = ecx.expr_uint(span, loc.line);
+    let fail_expr = match test.should_fail {
+        No => ecx.expr_path(test_path("No")),
+        Yes => ecx.expr_path(test_path("Yes")),
+        // self::test::YesWithMessage(&["...", ...])
+        YesWithMessage(ref msgs) => {
+            let msgs_expr = ecx.expr_vec_slice(span, msgs.iter().map(|msg| {
+                ecx.expr_str(span, msg.clone())
+            }).collect());
+            ecx.expr_call(span,
+                          ecx.expr_path(test_path("YesWithMessage")),
+                          vec![msgs_expr])
+        }
+    };
 
 
     let mut visible_path = vec![cx.reexport_mod_ident.clone()];
-    visible_path.extend(path.move_iter());
+    match test.called_as {
+        // Trait::method, or Type::method for a type brought in with `use`,
+        // is called through its copy alongside the trait or impl
+        Some(copy_ident) => {
+            visible_path.push_all(path.slice_to(path.len() - 2));
+            visible_path.push(copy_ident);
+        }
+        None => visible_path.extend(path.move_iter())
+    }
 
     let fn_expr = ecx.expr_path(ecx.path_global(span, visible_path));
 
-    let variant_name = if test.bench { "StaticBenchFn" } else { "StaticTestFn" };
-    // self::test::$variant_name($fn_expr)
-    let testfn_expr = ecx.expr_call(span, ecx.expr_path(test_path(variant_name)), vec![fn_expr]);
+    // Tests returning a value, such as a `Result`, are run through a wrapper
+    // which fails the test on `Err` or `None`, and so are static methods,
+    // which can't be named in a constant, and tests returning `!`, which
+    // aren't a `fn()`:
+    // { fn wrapper() { self::test::assert_test_result($fn_expr()) } wrapper }
+    // A test with an invalid signature can't be called at all, so its
+    // wrapper just fails, and a benchmark run as a test is handed to
+    // self::test::run_bench_once. A test with a fixture is called with a
+    // reference to what the fixture returns: $fn_expr(&$fixture_expr())
+    let wrapper_body = if test.invalid_signature {
+        Some(ecx.expr_fail(span, InternedString::new("test has an invalid signature")))
+    } else if test.bench_as_test {
+        Some(ecx.expr_call(span, ecx.expr_path(test_path("run_bench_once")), vec![fn_expr]))
+    } else if test.returns_value || test.is_method || test.diverges ||
+              test.fixture.is_some() {
+        let args = match test.fixture {
+            Some(ref fixture_path) => {
+                let mut visible_fixture_path = vec![cx.reexport_mod_ident.clone()];
+                visible_fixture_path.extend(fixture_path.iter().map(|id| *id));
+                let fixture_expr = ecx.expr_path(ecx.path_global(span, visible_fixture_path));
+                vec![ecx.expr_addr_of(span, ecx.expr_call(span, fixture_expr, vec![]))]
+            }
+            None => vec![]
+        };
+        let mut call_expr = ecx.expr_call(span, fn_expr, args);
+        // a #[should_fail] test returning a `Result` passes only by
+        // failing, whatever it returns, and a #[should_err] one only by
+        // returning an `Err`
+        if test.should_err {
+            call_expr = ecx.expr_call(span,
+                                      ecx.expr_path(test_path("assert_test_err")),
+                                      vec![call_expr]);
+        } else if test.returns_result && test.should_fail != No {
+            call_expr = quote_expr!(ecx, { let _ = $call_expr; });
+        } else if test.returns_value {
+            call_expr = ecx.expr_call(span,
+                                      ecx.expr_path(test_path("assert_test_result")),
+                                      vec![call_expr]);
+        }
+        Some(call_expr)
+    } else {
+        None
+    };
+    let fn_expr = match wrapper_body {
+        Some(body) => quote_expr!(ecx, { fn wrapper() { $body } wrapper }),
+        None => fn_expr
+    };
+
+    // With #![test_harness(wrap = "...")], tests (but not benchmarks) are
+    // handed to the crate's hook along with their name, which has to be a
+    // `fn(&'static str, fn())`:
+    // { fn traced() { ::$wrap("$name", $fn_expr) } traced }
+    let fn_expr = match cx.harness.wrap {
+        Some(ref wrap) if !test.bench => {
+            let wrap_path = wrap.get().split_str("::").map(|s| ecx.ident_of(s)).collect();
+            let call = ecx.expr_call(span,
+                                     ecx.expr_path(ecx.path_global(span, wrap_path)),
+                                     vec![ecx.expr_str(span, test_display_name(test)),
+                                          fn_expr]);
+            quote_expr!(ecx, { fn traced() { $call } traced })
+        }
+        _ => fn_expr
+    };
+
+    let variant = ecx.ident_of(if test.bench { "StaticBenchFn" } else { "StaticTestFn" });
+    let (ignore, allow_fail, serial) = (test.ignore, test.allow_fail, test.serial);
+    let (retries, weight) = (test.retries, test.weight);
+    let order_group_expr = match test.order_group {
+        Some(ref group) => some(ecx.expr_str(span, group.clone())),
+        None => none()
+    };
+    let run_after_expr = ecx.expr_vec_slice(span, test.run_after.iter().map(|group| {
+        ecx.expr_str(span, group.clone())
+    }).collect());
+    let bench_unit_expr = match test.bench_unit {
+        Some(ref unit) => some(ecx.expr_str(span, unit.clone())),
+        None => none()
+    };
 
-    // self::test::TestDescAndFn { ... }
-    ecx.expr_struct(span,
-                    test_path("TestDescAndFn"),
-                    vec![field("desc", desc_expr),
-                         field("testfn", testfn_expr)])
+    quote_expr!(ecx,
+        self::$test_id::TestDescAndFn {
+            desc: self::$test_id::TestDesc {
+                name: self::$test_id::StaticTestName($name_expr),
+                ignore: $ignore,
+                should_fail: $fail_expr,
+                ignore_reason: $ignore_reason_expr,
+                timeout_ms: $timeout_expr,
+                source_file: $file_expr,
+                start_line: $line_expr,
+                iterations: $iterations_expr,
+                groups: $groups_expr,
+                description: $description_expr,
+                allow_fail: $allow_fail,
+                retries: $retries,
+                module_path: $module_path_expr,
+                weight: $weight,
+                serial: $serial,
+                order_group: $order_group_expr,
+                run_after: $run_after_expr,
+                bench_unit: $bench_unit_expr,
+            },
+            testfn: self::$test_id::$variant($fn_expr),
+        }
+    )
 }
@@ -284,7 +284,22 @@ impl Collector {
             desc: testing::TestDesc {
                 name: testing::DynTestName(name),
                 ignore: should_ignore,
-                should_fail: false, // compiler failures are test failures
+                should_fail: testing::No, // compiler failures are test failures
+                ignore_reason: None,
+                timeout_ms: None,
+                source_file: "",
+                start_line: 0,
+                iterations: None,
+                groups: &[],
+                description: None,
+                allow_fail: false,
+                retries: 0,
+                module_path: "",
+                weight: 1,
+                serial: false,
+                order_group: None,
+                run_after: &[],
+                bench_unit: None,
             },
             testfn: testing::DynTestFn(proc() {
                 runtest(test.as_slice(),
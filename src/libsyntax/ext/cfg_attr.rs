@@ -0,0 +1,45 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::gc::{Gc, GC};
+
+use ast;
+use attr;
+use codemap::Span;
+use ext::base::ExtCtxt;
+use ext::build::AstBuilder;
+use parse::token::InternedString;
+
+/// Expands `#[cfg_attr(<cfg pattern>, <attr>)]` to `#[<attr>]` if the cfg
+/// pattern matches the current compilation environment, and to nothing
+/// otherwise.
+pub fn expand(cx: &mut ExtCtxt, sp: Span, mi: Gc<ast::MetaItem>, it: Gc<ast::Item>)
+              -> Gc<ast::Item> {
+    let (cfg, attr) = match mi.node {
+        ast::MetaList(_, ref mis) if mis.len() == 2 => (mis[0], mis[1]),
+        _ => {
+            cx.span_err(sp, "expected `#[cfg_attr(<cfg pattern>, <attr>)]`");
+            return it;
+        }
+    };
+
+    let mut out = (*it).clone();
+    if cfg_matches(cx, cfg) {
+        out.attrs.push(cx.attribute(attr.span, attr));
+    }
+
+    box(GC) out
+}
+
+fn cfg_matches(cx: &mut ExtCtxt, cfg: Gc<ast::MetaItem>) -> bool {
+    // test_cfg searches for meta items looking like `cfg(foo, ...)`
+    let meta_cfg = cx.meta_list(cfg.span, InternedString::new("cfg"), vec![cfg]);
+    attr::test_cfg(cx.cfg().as_slice(), Some(meta_cfg).move_iter())
+}
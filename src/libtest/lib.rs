@@ -41,7 +41,7 @@ extern crate serialize;
 extern crate term;
 extern crate time;
 
-use std::collections::TreeMap;
+use std::collections::{HashMap, TreeMap};
 use stats::Stats;
 use time::precise_time_ns;
 use getopts::{OptGroup, optflag, optopt};
@@ -51,6 +51,7 @@ use serialize::json::{Json, ToJson};
 use term::Terminal;
 use term::color::{Color, RED, YELLOW, GREEN, CYAN};
 
+use std::any::{Any, AnyRefExt};
 use std::cmp;
 use std::f64;
 use std::fmt;
@@ -65,13 +66,17 @@ use std::task::TaskBuilder;
 
 // to be used by rustc to compile tests in libtest
 pub mod test {
-    pub use {Bencher, TestName, TestResult, TestDesc,
-             TestDescAndFn, TestOpts, TrFailed, TrIgnored, TrOk,
+    pub use {Bencher, TestName, TestResult, TestDesc, HARNESS_VERSION,
+             TestDescAndFn, TestOpts, TrFailed, TrAllowedFail, TrIgnored, TrOk,
+             ShouldFail, No, Yes, YesWithMessage,
              Metric, MetricMap, MetricAdded, MetricRemoved,
              MetricChange, Improvement, Regression, LikelyNoise,
              StaticTestFn, StaticTestName, DynTestName, DynTestFn,
              run_test, test_main, test_main_static, filter_tests,
-             parse_opts, StaticBenchFn};
+             parse_opts, StaticBenchFn, assert_test_result, assert_test_err,
+             Termination, run_bench_once, run_static_test,
+             test_main_static_with_extra, test_main_static_os_args,
+             test_main_static_with_max_threads};
 }
 
 pub mod stats;
@@ -179,13 +184,71 @@ pub struct Bencher {
     pub bytes: u64,
 }
 
+/// Whether a test is expected to fail, and optionally substrings of which
+/// the failure message must contain at least one.
+#[deriving(Clone, Show, PartialEq, Eq, Hash)]
+pub enum ShouldFail {
+    No,
+    Yes,
+    YesWithMessage(&'static [&'static str])
+}
+
+/// The version of the descriptors the harness hands the test runner, which
+/// the harness gives as `HARNESS_VERSION` in the test module. This has to
+/// be bumped whenever the fields of `TestDesc` change, so that a runner
+/// loading the tests from a built crate can refuse ones it doesn't
+/// understand.
+pub static HARNESS_VERSION: u32 = 1;
+
 // The definition of a single test. A test runner will run a list of
 // these.
 #[deriving(Clone, Show, PartialEq, Eq, Hash)]
 pub struct TestDesc {
     pub name: TestName,
     pub ignore: bool,
-    pub should_fail: bool,
+    pub should_fail: ShouldFail,
+    pub ignore_reason: Option<&'static str>,
+    // A deadline for the test, given by #[test(timeout_ms = N)], for runners
+    // which enforce one. This runner doesn't.
+    pub timeout_ms: Option<u64>,
+    // Where the test is defined, for tools that want to point at it. These
+    // are "" and 0 when the location isn't known.
+    pub source_file: &'static str,
+    pub start_line: uint,
+    // For benchmarks, a fixed number of iterations to time instead of
+    // working one out.
+    pub iterations: Option<uint>,
+    // The categories given by #[test_group = ...]. This runner doesn't select
+    // tests by them; they're passed on for tools which do.
+    pub groups: &'static [&'static str],
+    // The first line of the test's doc comment, if it has one. This runner
+    // doesn't show it; it's passed on for tools listing the tests.
+    pub description: Option<&'static str>,
+    // A failure is recorded as allowed rather than failing the run, for
+    // tests of known bugs.
+    pub allow_fail: bool,
+    // How many times a flaky test may be rerun after failing, given by
+    // #[test(retries = N)]. Zero runs it once.
+    pub retries: uint,
+    // The path of the module containing the test, such as "a::b" for the
+    // test a::b::c, or "" for a test at the top of the crate. This runner
+    // doesn't use it; it's passed on for tools grouping tests by module.
+    pub module_path: &'static str,
+    // How costly the test is relative to others, for splitting tests into
+    // evenly sized shards, given by #[test(weight = N)]. Defaults to 1.
+    pub weight: uint,
+    // Set by #[test(serial)] for tests which mustn't run at the same time as
+    // any other, such as those touching global state.
+    pub serial: bool,
+    // The group the test belongs to for ordering, given by
+    // #[test(group = "...")].
+    pub order_group: Option<&'static str>,
+    // The groups whose tests must all have finished before this test starts,
+    // given by #[test(after = "...")].
+    pub run_after: &'static [&'static str],
+    // For benchmarks, what the throughput is measured in when it isn't
+    // MB/s, given by #[bench(unit = "...")].
+    pub bench_unit: Option<&'static str>,
 }
 
 #[deriving(Show)]
@@ -231,12 +294,19 @@ pub type MetricDiff = TreeMap<String,MetricChange>;
 // The default console test runner. It accepts the command line
 // arguments and a vector of test_descs.
 pub fn test_main(args: &[String], tests: Vec<TestDescAndFn> ) {
-    let opts =
+    test_main_with(args, tests, |_| {})
+}
+
+// Runs the tests as `test_main` does, with the options parsed from `args`
+// adjusted by `adjust` first.
+fn test_main_with(args: &[String], tests: Vec<TestDescAndFn>, adjust: |&mut TestOpts|) {
+    let mut opts =
         match parse_opts(args) {
             Some(Ok(o)) => o,
             Some(Err(msg)) => fail!("{}", msg),
             None => return
         };
+    adjust(&mut opts);
     match run_tests_console(&opts, tests) {
         Ok(true) => {}
         Ok(false) => fail!("Some tests failed"),
@@ -252,16 +322,126 @@ pub fn test_main(args: &[String], tests: Vec<TestDescAndFn> ) {
 // semantics into parallel test runners, which in turn requires a ~[]
 // rather than a &[].
 pub fn test_main_static(args: &[String], tests: &[TestDescAndFn]) {
-    let owned_tests = tests.iter().map(|t| {
-        match t.testfn {
-            StaticTestFn(f) => TestDescAndFn { testfn: StaticTestFn(f), desc: t.desc.clone() },
-            StaticBenchFn(f) => TestDescAndFn { testfn: StaticBenchFn(f), desc: t.desc.clone() },
-            _ => fail!("non-static tests passed to test::test_main_static")
-        }
-    }).collect();
+    test_main(args, owned_static_tests(tests))
+}
+
+/// Like `test_main_static`, but reads the arguments from the OS itself, for
+/// harnesses generated in `#![no_std]` crates, which can't get at them.
+pub fn test_main_static_os_args(tests: &[TestDescAndFn]) {
+    test_main_static(os::args().as_slice(), tests)
+}
+
+/// Like `test_main_static`, but also runs `extra`, a list of tests built
+/// outside of the crate's own harness, such as doctests.
+pub fn test_main_static_with_extra(args: &[String],
+                                   tests: &[TestDescAndFn],
+                                   extra: &[TestDescAndFn]) {
+    let mut owned_tests = owned_static_tests(tests);
+    owned_tests.push_all_move(owned_static_tests(extra));
     test_main(args, owned_tests)
 }
 
+/// Like `test_main_static`, but runs no more than `max_threads` tests at
+/// once, for harnesses built with `#![test_harness(max_threads = N)]`.
+pub fn test_main_static_with_max_threads(args: &[String],
+                                         tests: &[TestDescAndFn],
+                                         max_threads: uint) {
+    test_main_with(args, owned_static_tests(tests), |opts| {
+        opts.max_threads = Some(max_threads);
+    })
+}
+
+fn owned_static_tests(tests: &[TestDescAndFn]) -> Vec<TestDescAndFn> {
+    tests.iter().map(owned_static_test).collect()
+}
+
+fn owned_static_test(t: &TestDescAndFn) -> TestDescAndFn {
+    match t.testfn {
+        StaticTestFn(f) => TestDescAndFn { testfn: StaticTestFn(f), desc: t.desc.clone() },
+        StaticBenchFn(f) => TestDescAndFn { testfn: StaticBenchFn(f), desc: t.desc.clone() },
+        _ => fail!("non-static tests passed to test::test_main_static")
+    }
+}
+
+/// Runs a single test from a static list of tests, in a task of its own,
+/// returning whether it passed. This is what the `run_one` function of a
+/// harness built with `-Z test-run-one` calls. The test is run even if it's
+/// ignored, as it was picked out by name, and its output isn't captured.
+pub fn run_static_test(test: &TestDescAndFn) -> bool {
+    let mut test = owned_static_test(test);
+    test.desc.ignore = false;
+    let mut opts = TestOpts::new();
+    opts.run_tests = true;
+    opts.nocapture = true;
+
+    let (tx, rx) = channel::<MonitorMsg>();
+    run_test(&opts, false, test, tx);
+    let (_, result, _) = rx.recv();
+    match result {
+        TrOk | TrAllowedFail | TrMetrics(_) | TrBench(_) => true,
+        TrFailed | TrIgnored => false,
+    }
+}
+
+/// A value which a `#[test]` function can return in place of `()`, saying
+/// whether the test passed.
+pub trait Termination {
+    /// Reports how the test went as a code, zero if it passed and anything
+    /// else if it failed. A failure can instead be explained by failing
+    /// with a message, as `Err` and `None` are.
+    fn report(self) -> int;
+}
+
+impl Termination for () {
+    fn report(self) -> int { 0 }
+}
+
+impl Termination for int {
+    fn report(self) -> int { self }
+}
+
+impl<E: Show> Termination for Result<(), E> {
+    fn report(self) -> int {
+        match self {
+            Ok(()) => 0,
+            Err(e) => fail!("test returned an error: {}", e),
+        }
+    }
+}
+
+impl Termination for Option<()> {
+    fn report(self) -> int {
+        match self {
+            Some(()) => 0,
+            None => fail!("test returned `None`"),
+        }
+    }
+}
+
+/// Checks the value returned by a `#[test]` function declared to return
+/// something other than `()`. The generated test harness calls this so that
+/// a value reporting a non-zero code fails the test.
+pub fn assert_test_result<T: Termination>(result: T) {
+    let code = result.report();
+    if code != 0 {
+        fail!("test returned failure code {}", code);
+    }
+}
+
+/// Checks the value returned by a `#[should_err]` test, which passes only if
+/// it returns an `Err`.
+pub fn assert_test_err<T, E>(result: Result<T, E>) {
+    if result.is_ok() {
+        fail!("test returned `Ok` where an error was expected");
+    }
+}
+
+/// Runs a benchmark for a single iteration, which is how a benchmark marked
+/// `#[bench(as_test)]` is run as a test.
+pub fn run_bench_once(f: fn(&mut Bencher)) {
+    bench::run_once(|b| f(b))
+}
+
 pub enum ColorConfig {
     AutoColor,
     AlwaysColor,
@@ -280,10 +460,12 @@ pub struct TestOpts {
     pub logfile: Option<Path>,
     pub nocapture: bool,
     pub color: ColorConfig,
+    // The most tests to run at once, whatever RUST_TEST_TASKS says, for
+    // suites which know they can't run more safely.
+    pub max_threads: Option<uint>,
 }
 
 impl TestOpts {
-    #[cfg(test)]
     fn new() -> TestOpts {
         TestOpts {
             filter: None,
@@ -297,6 +479,7 @@ impl TestOpts {
             logfile: None,
             nocapture: false,
             color: AutoColor,
+            max_threads: None,
         }
     }
 }
@@ -347,16 +530,45 @@ environment variable. Logging is not captured by default.
 Test Attributes:
 
     #[test]        - Indicates a function is a test to be run. This function
-                     takes no arguments.
+                     takes no arguments, and returns either () or a Result,
+                     in which case returning Err fails the test. A deadline,
+                     which this runner doesn't enforce, may be given as
+                     #[test(timeout_ms = N)], and a number of retries for a
+                     flaky test as #[test(retries = N)],
+                     and a relative cost for sharding as
+                     #[test(weight = N)]. A test marked #[test(serial)] is
+                     never run at the same time as another test.
     #[bench]       - Indicates a function is a benchmark to be run. This
-                     function takes one argument (test::Bencher).
+                     function takes one argument (test::Bencher). A fixed
+                     iteration count may be given as
+                     #[bench(iterations = N)], and #[bench(as_test)] runs the
+                     benchmark once, as a test. The throughput is given in
+                     a unit other than MB/s by #[bench(unit = ...)], and
+                     #[bench(cfg(...))] only builds the benchmark in on
+                     certain configurations.
     #[should_fail] - This function (also labeled with #[test]) will only pass if
                      the code causes a failure (an assertion failure or fail!)
+                     An `expected` string may be given, as in
+                     #[should_fail(expected = ...)], to require that the
+                     failure message contain that text, or several, as in
+                     #[should_fail(expected(any = ..., any = ...))], to accept
+                     any one of them, and a cfg, as in
+                     #[should_fail(cfg(...))], to only expect a failure on
+                     certain configurations. #[should_panic] is accepted as
+                     a synonym.
     #[ignore]      - When applied to a function which is already attributed as a
                      test, then the test runner will ignore these tests during
                      normal test runs. Running with --ignored will run these
-                     tests. This may also be written as #[ignore(cfg(...))] to
-                     ignore the test on certain configurations.",
+                     tests. This may also be written as #[ignore(cfg(...))] or
+                     #[test(ignore_if(...))] to ignore the test on certain
+                     configurations, or as #[ignore = ...] to give a reason
+                     for ignoring it. Both can be given together, as in
+                     #[ignore(cfg(...), reason = ...)].
+    #[test_group = ...] - Puts a test in a named category. This may be given
+                     more than once. The categories are passed on to other
+                     tools; this runner doesn't select tests by them.
+    #[allow_fail]  - A failure of this test is reported, but doesn't fail the
+                     test run.",
              usage = getopts::usage(message.as_slice(),
                                     optgroups().as_slice()));
 }
@@ -431,6 +643,7 @@ pub fn parse_opts(args: &[String]) -> Option<OptRes> {
         logfile: logfile,
         nocapture: nocapture,
         color: color,
+        max_threads: None,
     };
 
     Some(Ok(test_opts))
@@ -467,6 +680,7 @@ pub struct BenchSamples {
 pub enum TestResult {
     TrOk,
     TrFailed,
+    TrAllowedFail,
     TrIgnored,
     TrMetrics(MetricMap),
     TrBench(BenchSamples),
@@ -484,6 +698,7 @@ struct ConsoleTestState<T> {
     total: uint,
     passed: uint,
     failed: uint,
+    allowed_fail: uint,
     ignored: uint,
     measured: uint,
     metrics: MetricMap,
@@ -510,6 +725,7 @@ impl<T: Writer> ConsoleTestState<T> {
             total: 0u,
             passed: 0u,
             failed: 0u,
+            allowed_fail: 0u,
             ignored: 0u,
             measured: 0u,
             metrics: MetricMap::new(),
@@ -526,6 +742,10 @@ impl<T: Writer> ConsoleTestState<T> {
         self.write_pretty("FAILED", term::color::RED)
     }
 
+    pub fn write_allowed_fail(&mut self) -> io::IoResult<()> {
+        self.write_pretty("FAILED (allowed)", term::color::YELLOW)
+    }
+
     pub fn write_ignored(&mut self) -> io::IoResult<()> {
         self.write_pretty("ignored", term::color::YELLOW)
     }
@@ -591,11 +811,19 @@ impl<T: Writer> ConsoleTestState<T> {
         self.write_plain(format!("test {} ... ", name).as_slice())
     }
 
-    pub fn write_result(&mut self, result: &TestResult) -> io::IoResult<()> {
+    pub fn write_result(&mut self, test: &TestDesc,
+                        result: &TestResult) -> io::IoResult<()> {
         try!(match *result {
             TrOk => self.write_ok(),
             TrFailed => self.write_failed(),
-            TrIgnored => self.write_ignored(),
+            TrAllowedFail => self.write_allowed_fail(),
+            TrIgnored => {
+                try!(self.write_ignored());
+                match test.ignore_reason {
+                    Some(reason) => self.write_plain(format!(", {}", reason).as_slice()),
+                    None => Ok(())
+                }
+            }
             TrMetrics(ref mm) => {
                 try!(self.write_metric());
                 self.write_plain(format!(": {}", fmt_metrics(mm)).as_slice())
@@ -603,7 +831,8 @@ impl<T: Writer> ConsoleTestState<T> {
             TrBench(ref bs) => {
                 try!(self.write_bench());
                 self.write_plain(format!(": {}",
-                                         fmt_bench_samples(bs)).as_slice())
+                                         fmt_bench_samples_in(bs, bench_unit(test)))
+                                     .as_slice())
             }
         });
         self.write_plain("\n")
@@ -617,9 +846,10 @@ impl<T: Writer> ConsoleTestState<T> {
                 let s = format!("{} {}\n", match *result {
                         TrOk => "ok".to_string(),
                         TrFailed => "failed".to_string(),
+                        TrAllowedFail => "failed (allowed)".to_string(),
                         TrIgnored => "ignored".to_string(),
                         TrMetrics(ref mm) => fmt_metrics(mm),
-                        TrBench(ref bs) => fmt_bench_samples(bs)
+                        TrBench(ref bs) => fmt_bench_samples_in(bs, bench_unit(test))
                     }, test.name.as_slice());
                 o.write(s.as_bytes())
             }
@@ -708,7 +938,8 @@ impl<T: Writer> ConsoleTestState<T> {
     pub fn write_run_finish(&mut self,
                             ratchet_metrics: &Option<Path>,
                             ratchet_pct: Option<f64>) -> io::IoResult<bool> {
-        assert!(self.passed + self.failed + self.ignored + self.measured == self.total);
+        assert!(self.passed + self.failed + self.allowed_fail + self.ignored +
+                self.measured == self.total);
 
         let ratchet_success = match *ratchet_metrics {
             None => true,
@@ -742,8 +973,14 @@ impl<T: Writer> ConsoleTestState<T> {
         } else {
             try!(self.write_failed());
         }
-        let s = format!(". {} passed; {} failed; {} ignored; {} measured\n\n",
-                        self.passed, self.failed, self.ignored, self.measured);
+        let s = if self.allowed_fail > 0 {
+            format!(". {} passed; {} failed ({} allowed); {} ignored; {} measured\n\n",
+                    self.passed, self.failed + self.allowed_fail, self.allowed_fail,
+                    self.ignored, self.measured)
+        } else {
+            format!(". {} passed; {} failed; {} ignored; {} measured\n\n",
+                    self.passed, self.failed, self.ignored, self.measured)
+        };
         try!(self.write_plain(s.as_slice()));
         return Ok(success);
     }
@@ -759,11 +996,22 @@ pub fn fmt_metrics(mm: &MetricMap) -> String {
 }
 
 pub fn fmt_bench_samples(bs: &BenchSamples) -> String {
+    fmt_bench_samples_in(bs, "MB/s")
+}
+
+fn bench_unit(desc: &TestDesc) -> &'static str {
+    desc.bench_unit.unwrap_or("MB/s")
+}
+
+// Formats the samples with the throughput labelled as `unit`, which the
+// bench's `bytes` are then counted in.
+fn fmt_bench_samples_in(bs: &BenchSamples, unit: &str) -> String {
     if bs.mb_s != 0 {
-        format!("{:>9} ns/iter (+/- {}) = {} MB/s",
+        format!("{:>9} ns/iter (+/- {}) = {} {}",
              bs.ns_iter_summ.median as uint,
              (bs.ns_iter_summ.max - bs.ns_iter_summ.min) as uint,
-             bs.mb_s)
+             bs.mb_s,
+             unit)
     } else {
         format!("{:>9} ns/iter (+/- {})",
              bs.ns_iter_summ.median as uint,
@@ -780,9 +1028,10 @@ pub fn run_tests_console(opts: &TestOpts, tests: Vec<TestDescAndFn> ) -> io::IoR
             TeWait(ref test, padding) => st.write_test_start(test, padding),
             TeResult(test, result, stdout) => {
                 try!(st.write_log(&test, &result));
-                try!(st.write_result(&result));
+                try!(st.write_result(&test, &result));
                 match result {
                     TrOk => st.passed += 1,
+                    TrAllowedFail => st.allowed_fail += 1,
                     TrIgnored => st.ignored += 1,
                     TrMetrics(mm) => {
                         let tname = test.name.as_slice();
@@ -843,17 +1092,9 @@ pub fn run_tests_console(opts: &TestOpts, tests: Vec<TestDescAndFn> ) -> io::IoR
 fn should_sort_failures_before_printing_them() {
     use std::io::MemWriter;
 
-    let test_a = TestDesc {
-        name: StaticTestName("a"),
-        ignore: false,
-        should_fail: false
-    };
+    let test_a = tests::desc("a");
 
-    let test_b = TestDesc {
-        name: StaticTestName("b"),
-        ignore: false,
-        should_fail: false
-    };
+    let test_b = tests::desc("b");
 
     let mut st = ConsoleTestState {
         log_out: None,
@@ -862,6 +1103,7 @@ fn should_sort_failures_before_printing_them() {
         total: 0u,
         passed: 0u,
         failed: 0u,
+        allowed_fail: 0u,
         ignored: 0u,
         measured: 0u,
         max_name_len: 10u,
@@ -917,33 +1159,49 @@ fn run_tests(opts: &TestOpts,
 
     // It's tempting to just spawn all the tests at once, but since we have
     // many tests that run in other processes we would be making a big mess.
-    let concurrency = get_concurrency();
-
-    let mut remaining = filtered_tests;
-    remaining.reverse();
-    let mut pending = 0;
+    let concurrency = match opts.max_threads {
+        Some(max) => cmp::min(max, get_concurrency()),
+        None => get_concurrency()
+    };
 
     let (tx, rx) = channel::<MonitorMsg>();
 
-    while pending > 0 || !remaining.is_empty() {
-        while pending < concurrency && !remaining.is_empty() {
-            let test = remaining.pop().unwrap();
-            if concurrency == 1 {
-                // We are doing one test at a time so we can print the name
-                // of the test before we run it. Useful for debugging tests
-                // that hang forever.
-                try!(callback(TeWait(test.desc.clone(), test.testfn.padding())));
+    // Tests ordered with #[test(after = "...")] run in stages, each of which
+    // finishes before the next starts. Without any, there's just the one.
+    for stage in order_stages(filtered_tests).move_iter() {
+        let (serial_tests, mut remaining) = stage.partition(|e| e.desc.serial);
+        remaining.reverse();
+        let mut pending = 0;
+
+        while pending > 0 || !remaining.is_empty() {
+            while pending < concurrency && !remaining.is_empty() {
+                let test = remaining.pop().unwrap();
+                if concurrency == 1 {
+                    // We are doing one test at a time so we can print the name
+                    // of the test before we run it. Useful for debugging tests
+                    // that hang forever.
+                    try!(callback(TeWait(test.desc.clone(), test.testfn.padding())));
+                }
+                run_test(opts, !opts.run_tests, test, tx.clone());
+                pending += 1;
+            }
+
+            let (desc, result, stdout) = rx.recv();
+            if concurrency != 1 {
+                try!(callback(TeWait(desc.clone(), PadNone)));
             }
-            run_test(opts, !opts.run_tests, test, tx.clone());
-            pending += 1;
+            try!(callback(TeResult(desc, result, stdout)));
+            pending -= 1;
         }
 
-        let (desc, result, stdout) = rx.recv();
-        if concurrency != 1 {
-            try!(callback(TeWait(desc.clone(), PadNone)));
+        // Tests marked #[test(serial)] run one at a time once the rest of
+        // their stage has finished, so they never overlap with another test.
+        for t in serial_tests.move_iter() {
+            try!(callback(TeWait(t.desc.clone(), t.testfn.padding())));
+            run_test(opts, !opts.run_tests, t, tx.clone());
+            let (desc, result, stdout) = rx.recv();
+            try!(callback(TeResult(desc, result, stdout)));
         }
-        try!(callback(TeResult(desc, result, stdout)));
-        pending -= 1;
     }
 
     // All benchmarks run at the end, in serial.
@@ -957,6 +1215,49 @@ fn run_tests(opts: &TestOpts,
     Ok(())
 }
 
+// Splits the tests into the stages they run in: a test which runs after a
+// group of tests is in a later stage than every test in the group, and the
+// other tests are in the first. The stages keep the tests in their order.
+fn order_stages(tests: Vec<TestDescAndFn>) -> Vec<Vec<TestDescAndFn>> {
+    let mut stages = Vec::from_elem(tests.len(), 0u);
+    // The harness rejects groups which run after each other in a cycle, so
+    // this settles within a pass per test, but dynamic tests aren't checked.
+    for _ in range(0, tests.len()) {
+        let mut group_stages: HashMap<&'static str, uint> = HashMap::new();
+        for (test, &stage) in tests.iter().zip(stages.iter()) {
+            match test.desc.order_group {
+                Some(group) => {
+                    let latest = group_stages.find(&group).map_or(stage, |&s| cmp::max(s, stage));
+                    group_stages.insert(group, latest);
+                }
+                None => {}
+            }
+        }
+        let mut changed = false;
+        for (test, stage) in tests.iter().zip(stages.mut_iter()) {
+            for group in test.desc.run_after.iter() {
+                match group_stages.find(group) {
+                    Some(&s) if s >= *stage => {
+                        *stage = s + 1;
+                        changed = true;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let last_stage = stages.iter().max().map_or(0, |&s| s);
+    let mut staged = Vec::from_fn(last_stage + 1, |_| Vec::new());
+    for (test, stage) in tests.move_iter().zip(stages.move_iter()) {
+        staged.get_mut(stage).push(test);
+    }
+    staged
+}
+
 fn get_concurrency() -> uint {
     use std::rt;
     match os::getenv("RUST_TEST_TASKS") {
@@ -1010,12 +1311,17 @@ pub fn filter_tests(opts: &TestOpts, tests: Vec<TestDescAndFn>) -> Vec<TestDescA
     match opts.test_shard {
         None => filtered,
         Some((a,b)) => {
-            filtered.move_iter().enumerate()
-            // note: using a - 1 so that the valid shards, for example, are
-            // 1.2 and 2.2 instead of 0.2 and 1.2
-            .filter(|&(i,_)| i % b == (a - 1))
-            .map(|(_,t)| t)
-            .collect()
+            // each test goes to the shard with the least weight so far, or
+            // the first of them, so tests of equal weight are dealt out in
+            // turn
+            let mut loads = Vec::from_elem(b, 0u);
+            filtered.move_iter().filter(|test| {
+                let (shard, _) = loads.iter().enumerate().min_by(|&(_, &load)| load).unwrap();
+                *loads.get_mut(shard) += test.desc.weight;
+                // note: using a - 1 so that the valid shards, for example, are
+                // 1.2 and 2.2 instead of 0.2 and 1.2
+                shard == a - 1
+            }).collect()
         }
     }
 }
@@ -1055,19 +1361,25 @@ pub fn run_test(opts: &TestOpts,
 
             let stdout = reader.read_to_end().unwrap().move_iter().collect();
             let task_result = result_future.unwrap();
-            let test_result = calc_result(&desc, task_result.is_ok());
+            let test_result = calc_result(&desc, task_result);
             monitor_ch.send((desc.clone(), test_result, stdout));
         })
     }
 
     match testfn {
         DynBenchFn(bencher) => {
-            let bs = ::bench::benchmark(|harness| bencher.run(harness));
+            let bs = match desc.iterations {
+                Some(n) => ::bench::benchmark_n(n as u64, |harness| bencher.run(harness)),
+                None => ::bench::benchmark(|harness| bencher.run(harness)),
+            };
             monitor_ch.send((desc, TrBench(bs), Vec::new()));
             return;
         }
         StaticBenchFn(benchfn) => {
-            let bs = ::bench::benchmark(|harness| benchfn(harness));
+            let bs = match desc.iterations {
+                Some(n) => ::bench::benchmark_n(n as u64, |harness| benchfn(harness)),
+                None => ::bench::benchmark(|harness| benchfn(harness)),
+            };
             monitor_ch.send((desc, TrBench(bs), Vec::new()));
             return;
         }
@@ -1084,18 +1396,49 @@ pub fn run_test(opts: &TestOpts,
             return;
         }
         DynTestFn(f) => run_test_inner(desc, monitor_ch, opts.nocapture, f),
+        // A failing test is rerun until it passes or has used up its
+        // retries, and only the last run is reported. Only a static test
+        // can be, as running a dynamic one uses it up.
+        StaticTestFn(f) if desc.retries > 0 => {
+            let nocapture = opts.nocapture;
+            spawn(proc() {
+                let (tx, rx) = channel();
+                let mut retries = desc.retries;
+                loop {
+                    run_test_inner(desc.clone(), tx.clone(), nocapture, proc() f());
+                    match rx.recv() {
+                        (_, TrFailed, _) if retries > 0 => retries -= 1,
+                        msg => {
+                            monitor_ch.send(msg);
+                            break;
+                        }
+                    }
+                }
+            })
+        }
         StaticTestFn(f) => run_test_inner(desc, monitor_ch, opts.nocapture,
                                           proc() f())
     }
 }
 
-fn calc_result(desc: &TestDesc, task_succeeded: bool) -> TestResult {
-    if task_succeeded {
-        if desc.should_fail { TrFailed }
-        else { TrOk }
-    } else {
-        if desc.should_fail { TrOk }
-        else { TrFailed }
+fn calc_result(desc: &TestDesc, task_result: Result<(), Box<Any + Send>>) -> TestResult {
+    match (&desc.should_fail, task_result) {
+        (&No, Ok(())) |
+        (&Yes, Err(_)) => TrOk,
+        (&YesWithMessage(msgs), Err(ref err))
+            if fail_message(&**err).map_or(false, |e| {
+                msgs.iter().any(|msg| e.contains(*msg))
+            }) => TrOk,
+        _ if desc.allow_fail => TrAllowedFail,
+        _ => TrFailed,
+    }
+}
+
+// Extracts the message a task failed with, if it failed with a string
+fn fail_message<'a>(err: &'a Any + Send) -> Option<&'a str> {
+    match err.as_ref::<&'static str>() {
+        Some(s) => Some(*s),
+        None => err.as_ref::<String>().map(|s| s.as_slice()),
     }
 }
 
@@ -1301,6 +1644,19 @@ impl Bencher {
         f(self);
     }
 
+    // Times `n` iterations at a time, rather than working out how many
+    // iterations to time as `auto_bench` does.
+    pub fn fixed_bench(&mut self, n: u64, f: |&mut Bencher|) -> stats::Summary<f64> {
+        let samples : &mut [f64] = [0.0_f64, ..50];
+        for p in samples.mut_iter() {
+            self.bench_n(n, |x| f(x));
+            *p = self.ns_per_iter() as f64;
+        };
+
+        stats::winsorize(samples, 5.0);
+        stats::Summary::new(samples)
+    }
+
     // This is a more statistics-driven benchmark algorithm
     pub fn auto_bench(&mut self, f: |&mut Bencher|) -> stats::Summary<f64> {
 
@@ -1367,18 +1723,38 @@ impl Bencher {
 
 pub mod bench {
     use std::cmp;
+    use stats::Summary;
     use super::{Bencher, BenchSamples};
 
     pub fn benchmark(f: |&mut Bencher|) -> BenchSamples {
-        let mut bs = Bencher {
+        let mut bs = new_bencher();
+        let ns_iter_summ = bs.auto_bench(f);
+        samples(&bs, ns_iter_summ)
+    }
+
+    /// Benchmarks `f` with a fixed number of iterations per sample.
+    pub fn benchmark_n(n: u64, f: |&mut Bencher|) -> BenchSamples {
+        let mut bs = new_bencher();
+        let ns_iter_summ = bs.fixed_bench(n, f);
+        samples(&bs, ns_iter_summ)
+    }
+
+    /// Runs `f` for a single iteration, without timing it.
+    pub fn run_once(f: |&mut Bencher|) {
+        let mut bs = new_bencher();
+        bs.bench_n(1, f);
+    }
+
+    fn new_bencher() -> Bencher {
+        Bencher {
             iterations: 0,
             ns_start: 0,
             ns_end: 0,
             bytes: 0
-        };
-
-        let ns_iter_summ = bs.auto_bench(f);
+        }
+    }
 
+    fn samples(bs: &Bencher, ns_iter_summ: Summary<f64>) -> BenchSamples {
         let ns_iter = cmp::max(ns_iter_summ.median as u64, 1);
         let iter_s = 1_000_000_000 / ns_iter;
         let mb_s = (bs.bytes * iter_s) / 1_000_000;
@@ -1392,22 +1768,44 @@ pub mod bench {
 
 #[cfg(test)]
 mod tests {
-    use test::{TrFailed, TrIgnored, TrOk, filter_tests, parse_opts,
+    use test::{TrFailed, TrAllowedFail, TrIgnored, TrOk, filter_tests, parse_opts,
                TestDesc, TestDescAndFn, TestOpts, run_test,
+               No, Yes, YesWithMessage,
                Metric, MetricMap, MetricAdded, MetricRemoved,
                Improvement, Regression, LikelyNoise,
-               StaticTestName, DynTestName, DynTestFn};
+               StaticTestName, DynTestName, DynTestFn, StaticTestFn};
+    use super::{run_tests, TeResult};
     use std::io::TempDir;
 
+    // A descriptor for a plain test with nothing set but its name
+    pub fn desc(name: &'static str) -> TestDesc {
+        TestDesc {
+            name: StaticTestName(name),
+            ignore: false,
+            should_fail: No,
+            ignore_reason: None,
+            timeout_ms: None,
+            source_file: "",
+            start_line: 0,
+            iterations: None,
+            groups: &[],
+            description: None,
+            allow_fail: false,
+            retries: 0,
+            module_path: "",
+            weight: 1,
+            serial: false,
+            order_group: None,
+            run_after: &[],
+            bench_unit: None,
+        }
+    }
+
     #[test]
     pub fn do_not_run_ignored_tests() {
         fn f() { fail!(); }
         let desc = TestDescAndFn {
-            desc: TestDesc {
-                name: StaticTestName("whatever"),
-                ignore: true,
-                should_fail: false
-            },
+            desc: TestDesc { ignore: true, ..desc("whatever") },
             testfn: DynTestFn(proc() f()),
         };
         let (tx, rx) = channel();
@@ -1420,11 +1818,7 @@ mod tests {
     pub fn ignored_tests_result_in_ignored() {
         fn f() { }
         let desc = TestDescAndFn {
-            desc: TestDesc {
-                name: StaticTestName("whatever"),
-                ignore: true,
-                should_fail: false
-            },
+            desc: TestDesc { ignore: true, ..desc("whatever") },
             testfn: DynTestFn(proc() f()),
         };
         let (tx, rx) = channel();
@@ -1437,11 +1831,7 @@ mod tests {
     fn test_should_fail() {
         fn f() { fail!(); }
         let desc = TestDescAndFn {
-            desc: TestDesc {
-                name: StaticTestName("whatever"),
-                ignore: false,
-                should_fail: true
-            },
+            desc: TestDesc { should_fail: Yes, ..desc("whatever") },
             testfn: DynTestFn(proc() f()),
         };
         let (tx, rx) = channel();
@@ -1453,18 +1843,69 @@ mod tests {
     #[test]
     fn test_should_fail_but_succeeds() {
         fn f() { }
+        let desc = TestDescAndFn {
+            desc: TestDesc { should_fail: Yes, ..desc("whatever") },
+            testfn: DynTestFn(proc() f()),
+        };
+        let (tx, rx) = channel();
+        run_test(&TestOpts::new(), false, desc, tx);
+        let (_, res, _) = rx.recv();
+        assert!(res == TrFailed);
+    }
+
+    #[test]
+    fn test_should_fail_good_message() {
+        fn f() { fail!("an error message"); }
+        let desc = TestDescAndFn {
+            desc: TestDesc { should_fail: YesWithMessage(&["error message"]), ..desc("whatever") },
+            testfn: DynTestFn(proc() f()),
+        };
+        let (tx, rx) = channel();
+        run_test(&TestOpts::new(), false, desc, tx);
+        let (_, res, _) = rx.recv();
+        assert!(res == TrOk);
+    }
+
+    #[test]
+    fn test_should_fail_bad_message() {
+        fn f() { fail!("an error message"); }
+        let desc = TestDescAndFn {
+            desc: TestDesc { should_fail: YesWithMessage(&["foobar"]), ..desc("whatever") },
+            testfn: DynTestFn(proc() f()),
+        };
+        let (tx, rx) = channel();
+        run_test(&TestOpts::new(), false, desc, tx);
+        let (_, res, _) = rx.recv();
+        assert!(res == TrFailed);
+    }
+
+    #[test]
+    fn test_should_fail_any_message() {
+        fn f() { fail!("an error message"); }
         let desc = TestDescAndFn {
             desc: TestDesc {
-                name: StaticTestName("whatever"),
-                ignore: false,
-                should_fail: true
+                should_fail: YesWithMessage(&["foobar", "error message"]),
+                ..desc("whatever")
             },
             testfn: DynTestFn(proc() f()),
         };
         let (tx, rx) = channel();
         run_test(&TestOpts::new(), false, desc, tx);
         let (_, res, _) = rx.recv();
-        assert!(res == TrFailed);
+        assert!(res == TrOk);
+    }
+
+    #[test]
+    fn test_allow_fail() {
+        fn f() { fail!(); }
+        let desc = TestDescAndFn {
+            desc: TestDesc { allow_fail: true, ..desc("whatever") },
+            testfn: DynTestFn(proc() f()),
+        };
+        let (tx, rx) = channel();
+        run_test(&TestOpts::new(), false, desc, tx);
+        let (_, res, _) = rx.recv();
+        assert!(res == TrAllowedFail);
     }
 
     #[test]
@@ -1500,19 +1941,11 @@ mod tests {
 
         let tests = vec!(
             TestDescAndFn {
-                desc: TestDesc {
-                    name: StaticTestName("1"),
-                    ignore: true,
-                    should_fail: false,
-                },
+                desc: TestDesc { ignore: true, ..desc("1") },
                 testfn: DynTestFn(proc() {}),
             },
             TestDescAndFn {
-                desc: TestDesc {
-                    name: StaticTestName("2"),
-                    ignore: false,
-                    should_fail: false
-                },
+                desc: desc("2"),
                 testfn: DynTestFn(proc() {}),
             });
         let filtered = filter_tests(&opts, tests);
@@ -1544,11 +1977,7 @@ mod tests {
             let mut tests = Vec::new();
             for name in names.iter() {
                 let test = TestDescAndFn {
-                    desc: TestDesc {
-                        name: DynTestName((*name).clone()),
-                        ignore: false,
-                        should_fail: false
-                    },
+                    desc: TestDesc { name: DynTestName((*name).clone()), ..desc("") },
                     testfn: DynTestFn(testfn),
                 };
                 tests.push(test);
@@ -1585,11 +2014,7 @@ mod tests {
         fn test_fn() {}
         let tests = names.iter().map(|name| {
             TestDescAndFn {
-                desc: TestDesc {
-                    name: DynTestName(name.to_string()),
-                    ignore: false,
-                    should_fail: false
-                },
+                desc: TestDesc { name: DynTestName(name.to_string()), ..desc("") },
                 testfn: DynTestFn(test_fn)
             }
         }).collect();
@@ -1604,6 +2029,75 @@ mod tests {
         }
     }
 
+    #[test]
+    pub fn failing_tests_are_retried() {
+        static mut RUNS: uint = 0;
+        fn fails_twice() {
+            let runs = unsafe {
+                RUNS += 1;
+                RUNS
+            };
+            assert!(runs > 2);
+        }
+
+        let test = TestDescAndFn {
+            desc: TestDesc { retries: 2, ..desc("flaky") },
+            testfn: StaticTestFn(fails_twice),
+        };
+        let (tx, rx) = channel();
+        run_test(&TestOpts::new(), false, test, tx);
+        let (_, res, _) = rx.recv();
+        assert!(res == TrOk);
+        assert_eq!(unsafe { RUNS }, 3);
+    }
+
+    #[test]
+    pub fn shards_are_balanced_by_weight() {
+        let mut opts = TestOpts::new();
+        opts.test_shard = Some((1, 2));
+
+        // "a" weighs as much as the other three together, so it has the
+        // first shard to itself
+        let descs = vec!(TestDesc { weight: 3, ..desc("a") }, desc("b"), desc("c"), desc("d"));
+        let tests = descs.move_iter().map(|desc| {
+            TestDescAndFn { desc: desc, testfn: DynTestFn(proc() {}) }
+        }).collect();
+        let filtered = filter_tests(&opts, tests);
+
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered.get(0).desc.name.as_slice(), "a");
+    }
+
+    #[test]
+    pub fn serial_tests_run_in_their_stage() {
+        let mut opts = TestOpts::new();
+        opts.run_tests = true;
+
+        // "a" is serial and in group x, which "b" and the serial "c" run
+        // after, and "d" isn't ordered at all
+        let descs = vec!(
+            TestDesc { serial: true, order_group: Some("x"), ..desc("a") },
+            TestDesc { run_after: &["x"], ..desc("b") },
+            TestDesc { serial: true, run_after: &["x"], ..desc("c") },
+            desc("d"));
+        let tests = descs.move_iter().map(|desc| {
+            TestDescAndFn { desc: desc, testfn: DynTestFn(proc() {}) }
+        }).collect();
+
+        let mut finished = Vec::new();
+        run_tests(&opts, tests, |event| {
+            match event {
+                TeResult(desc, _, _) => finished.push(desc.name.to_string()),
+                _ => {}
+            }
+            Ok(())
+        }).unwrap();
+
+        let expected = vec!("d".to_string(), "a".to_string(),
+                            "b".to_string(), "c".to_string());
+        assert_eq!(finished, expected);
+    }
+
     #[test]
     pub fn test_metricmap_compare() {
         let mut m1 = MetricMap::new();
@@ -0,0 +1,20 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+
+// compile-flags: --test
+
+#[bench]
+fn no_bencher() { } //~ ERROR functions used as benches
+//~^ NOTE try declaring it as `fn no_bencher(b: &mut Bencher)`
+
+#[bench]
+fn by_value(bencher: int) -> int { bencher } //~ ERROR functions used as benches
+//~^ NOTE try declaring it as `fn by_value(bencher: &mut Bencher)`
@@ -0,0 +1,19 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+
+extern crate test;
+
+#[bench(unit = 1024)] //~ ERROR a benchmark's unit must be given as a string
+fn throughput(_: &mut test::Bencher) {}
+
+#[bench(unit = "MiB/s")]
+fn labelled(_: &mut test::Bencher) {}
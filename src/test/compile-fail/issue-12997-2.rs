@@ -12,6 +12,8 @@
 
 //! Test that makes sure wrongly-typed bench functions are rejected
 
-// error-pattern:expected &-ptr, found int
 #[bench]
-fn bar(x: int) { }
+fn bar(x: int) { } //~ ERROR functions used as benches
+
+#[bench]
+fn baz(x: &int) { } //~ ERROR functions used as benches
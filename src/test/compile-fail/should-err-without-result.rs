@@ -0,0 +1,28 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+
+#[test]
+#[should_err] //~ ERROR `#[should_err]` can only be used on a test returning a `Result`
+fn returns_nothing() {}
+
+#[test]
+#[should_err]
+#[should_fail] //~^ ERROR `#[should_err]` and `#[should_fail]` cannot both be used on one test
+fn returns_err() -> Result<(), ()> {
+    Err(())
+}
+
+#[test]
+#[should_fail]
+fn returns_err_expecting_failure() -> Result<(), ()> { //~ WARNING this test is expected to fail
+    Err(())
+}
@@ -0,0 +1,19 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+
+#![feature(macro_rules)]
+
+macro_rules! make_test( //~ NOTE the test was generated by the macro `make_test!` defined here
+    ($name:ident) => (#[test] fn $name(x: int) {}) //~ ERROR functions used as tests can't take arguments
+)
+
+make_test!(takes_args)
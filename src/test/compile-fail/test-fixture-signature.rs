@@ -0,0 +1,22 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+
+fn setup() -> int { 1 }
+
+#[test(fixture = "setup")]
+fn no_argument() {} //~ ERROR tests with a fixture must take the fixture's value
+
+#[test(fixture = "setup")]
+fn two_arguments(x: &int, y: int) {} //~ ERROR functions used as tests can't take arguments
+
+#[test(fixture = 1)] //~ ERROR `fixture` must be given a path to a function
+fn not_a_string(x: &int) {}
@@ -0,0 +1,34 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+
+trait Fixture {
+    #[test]
+    fn with_self(&self) {} //~ ERROR methods taking `self` cannot be used as tests
+
+    #[bench]
+    fn required(); //~ ERROR tests in traits must have a body
+}
+
+trait Generic<T> {
+    #[test]
+    fn in_generic_trait() {} //~ ERROR tests in traits must be in a non-generic trait
+}
+
+trait UsesSelf {
+    #[test]
+    fn names_self() {
+        let _x: Option<Self> = None; //~ ERROR tests in traits can't use `Self` or `self`
+    }
+}
+
+#[test]
+fn free_test() {}
@@ -0,0 +1,29 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+
+extern crate test;
+
+#[test]
+fn outer() {
+    #[test] //~ ERROR `#[test]` functions must be declared at module scope
+    fn inner() {}
+
+    mod nested {
+        #[bench] //~ ERROR `#[bench]` functions must be declared at module scope
+        fn bench(_: &mut ::test::Bencher) {}
+    }
+}
+
+fn helper() {
+    #[test] //~ ERROR `#[test]` functions must be declared at module scope
+    fn hidden() {}
+}
@@ -0,0 +1,26 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+
+// compile-flags: --test
+
+struct Foo;
+
+impl Foo {
+    #[test]
+    fn with_self(&self) {} //~ ERROR methods taking `self` cannot be used as tests
+}
+
+struct Bar<T>;
+
+impl<T> Bar<T> {
+    #[test]
+    fn in_generic_impl() {} //~ ERROR tests in impls must be in a non-generic impl
+}
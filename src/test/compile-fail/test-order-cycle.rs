@@ -0,0 +1,21 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+
+// compile-flags: --test
+
+#[test(group = "a", after = "b")]
+fn first() {}
+
+#[test(group = "b", after = "a")]
+fn second() {} //~ ERROR test groups run after each other in a cycle: `a` after `b` after `a`
+
+#[test(group = "c", after = "c")]
+fn itself() {} //~ ERROR test groups run after each other in a cycle: `c` after `c`
@@ -0,0 +1,23 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+
+#[test]
+#[should_fail(expected(any(foo)))] //~ ERROR expected failure message must be of the form
+fn nested() {
+    fail!("foo")
+}
+
+#[test]
+#[should_fail(expected())] //~ ERROR expected failure message must be of the form
+fn empty() {
+    fail!("foo")
+}
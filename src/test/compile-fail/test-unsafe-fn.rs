@@ -0,0 +1,26 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+
+// compile-flags: --test
+
+// Check that all unsafe tests and benches are reported, rather than
+// stopping at the first one.
+
+extern crate test;
+
+#[test]
+unsafe fn foo() {} //~ ERROR unsafe functions cannot be used for tests
+
+#[test]
+unsafe fn bar() {} //~ ERROR unsafe functions cannot be used for tests
+
+#[bench]
+unsafe fn baz(_: &mut test::Bencher) {} //~ ERROR unsafe functions cannot be used for tests
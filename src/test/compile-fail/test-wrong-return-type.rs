@@ -0,0 +1,25 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+
+// compile-flags: --test
+
+// Any return type gets past collection, as typeck checks that it's a
+// test::Termination (see test-return-type-termination.rs), so only the
+// arguments and generics are reported here.
+
+#[test]
+fn takes_args(x: int, y: int) {} //~ ERROR functions used as tests can't take arguments
+
+#[test]
+fn generic<T>() {} //~ ERROR functions used as tests can't be generic
+
+#[test]
+fn generic_with_lifetime<'a, T>() {} //~ ERROR functions used as tests can't be generic
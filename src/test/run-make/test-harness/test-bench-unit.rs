@@ -0,0 +1,25 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate test;
+
+#[bench(unit = "MiB/s")]
+fn copy_mib(b: &mut test::Bencher) {
+    let src = Vec::from_elem(1024 * 1024, 0u8);
+    b.bytes = src.len() as u64;
+    b.iter(|| src.clone());
+}
+
+#[bench]
+fn copy_mb(b: &mut test::Bencher) {
+    let src = Vec::from_elem(1024 * 1024, 0u8);
+    b.bytes = src.len() as u64;
+    b.iter(|| src.clone());
+}
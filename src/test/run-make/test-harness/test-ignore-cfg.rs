@@ -17,3 +17,26 @@ fn shouldignore() {
 #[ignore(cfg(noignorecfg))]
 fn shouldnotignore() {
 }
+
+#[test(ignore_if(ignorecfg))]
+fn shouldignoreif() {
+}
+
+#[test(ignore_if(not(ignorecfg)))]
+fn shouldnotignoreif() {
+}
+
+#[test]
+#[ignore(cfg(ignorecfg), reason = "broken with ignorecfg")]
+fn ignorewithcfgandreason() {
+}
+
+#[test]
+#[ignore(cfg(noignorecfg), reason = "broken with noignorecfg")]
+fn notignoredwithcfgandreason() {
+}
+
+#[test]
+#[ignore(reason = "always broken")]
+fn ignorewithlistreason() {
+}
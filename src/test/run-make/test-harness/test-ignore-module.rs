@@ -0,0 +1,44 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#[ignore]
+mod ignored {
+    #[test]
+    fn inner() {
+        fail!()
+    }
+
+    mod nested {
+        #[test]
+        fn deeper() {
+            fail!()
+        }
+    }
+}
+
+#[ignore = "needs a database"]
+mod with_reason {
+    #[test]
+    fn query() {
+        fail!()
+    }
+}
+
+mod not_ignored {
+    #[test]
+    fn runs() {
+    }
+
+    #[test]
+    #[ignore]
+    fn ignored_itself() {
+        fail!()
+    }
+}
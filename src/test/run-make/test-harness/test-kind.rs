@@ -0,0 +1,27 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate test;
+
+#[test]
+fn a_unit_test() {
+}
+
+#[test(kind = "integration")]
+fn an_integration_test() {
+}
+
+#[test(kind = "smoke")]
+fn a_smoke_test() {
+}
+
+#[bench]
+fn a_bench(_: &mut test::Bencher) {
+}
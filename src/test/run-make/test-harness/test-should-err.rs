@@ -0,0 +1,27 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#[test]
+#[should_err]
+fn returns_err() -> Result<(), String> {
+    Err("expected".to_string())
+}
+
+#[test]
+#[should_fail]
+fn fails_instead_of_returning() -> Result<(), String> {
+    fail!("expected")
+}
+
+#[test]
+#[should_fail]
+fn returns_err_when_expected_to_fail() -> Result<(), String> {
+    Err("not a failure".to_string())
+}
@@ -0,0 +1,33 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+
+#[test]
+fn passes() {
+}
+
+#[test]
+#[should_fail]
+fn fails() {
+    fail!()
+}
+
+#[test]
+#[should_fail(expected(any = "boom", any = "bang"))]
+fn fails_with_message() {
+    fail!("boom")
+}
+
+#[test]
+#[should_fail]
+#[test_name = "renamed failure"]
+fn fails_renamed() {
+    fail!()
+}
@@ -0,0 +1,29 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+
+// A #[bench(keep)] function isn't stripped from a build without --test, so
+// it can still be called.
+
+extern crate test;
+
+use test::Bencher;
+
+static mut RUNS: uint = 0;
+
+#[bench(keep)]
+fn kept(b: &mut Bencher) {
+    b.iter(|| unsafe { RUNS += 1; });
+}
+
+pub fn main() {
+    test::run_bench_once(kept);
+    assert!(unsafe { RUNS } > 0);
+}
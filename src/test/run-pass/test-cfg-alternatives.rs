@@ -0,0 +1,50 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test --cfg fast
+// ignore-pretty: does not work well with `--test`
+
+// Of two tests sharing a name under opposite cfgs, only the one whose cfg is
+// active is collected and reexported: the crate is stripped by its cfgs
+// before the tests are looked for.
+
+#![test_harness(module = "my_tests")]
+
+extern crate test;
+
+#[cfg(fast)]
+#[test]
+fn t() {
+    assert!(cfg!(fast));
+}
+
+#[cfg(not(fast))]
+#[test]
+fn t() {
+    fail!("the test with the inactive cfg was run");
+}
+
+mod nested {
+    #[cfg(not(fast))]
+    #[test]
+    fn t() {
+        fail!("the test with the inactive cfg was run");
+    }
+
+    #[cfg(fast)]
+    #[test]
+    fn t() {}
+}
+
+#[test]
+fn one_of_each_is_described() {
+    let tests: &'static [test::TestDescAndFn] = ::my_tests::describe();
+    assert_eq!(tests.len(), 3);
+}
@@ -0,0 +1,29 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test --cfg ignorecfg
+// ignore-pretty: does not work well with `--test`
+
+#![feature(cfg_attr)]
+
+#[test]
+#[cfg_attr(ignorecfg, ignore)]
+fn ignored_under_cfg() {
+    fail!()
+}
+
+#[test]
+#[cfg_attr(failcfg, should_fail)]
+fn not_expected_to_fail() {
+}
+
+#[cfg_attr(test, test)]
+fn only_a_test_under_test() {
+}
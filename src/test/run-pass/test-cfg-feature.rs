@@ -0,0 +1,34 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+
+// compile-flags: --test --cfg feature="on"
+// ignore-pretty: does not work well with `--test`
+
+// Tests whose cfg is inactive are left out of the harness, along with
+// their reexports.
+
+#[test]
+#[cfg(feature = "on")]
+fn active() {}
+
+#[test]
+#[cfg(feature = "off")]
+fn inactive() {
+    fail!("a test with an inactive cfg was run");
+}
+
+mod gated {
+    #[test]
+    #[cfg(feature = "off")]
+    fn inactive() {
+        fail!("a test with an inactive cfg was run");
+    }
+}
@@ -0,0 +1,39 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+// ignore-pretty: does not work well with `--test`
+
+struct Db {
+    rows: uint,
+}
+
+fn setup_db() -> Db {
+    Db { rows: 3 }
+}
+
+mod fixtures {
+    pub fn empty() -> Vec<int> {
+        Vec::new()
+    }
+}
+
+#[test(fixture = "setup_db")]
+fn query(db: &Db) {
+    assert_eq!(db.rows, 3);
+}
+
+#[test(fixture = "fixtures::empty")]
+fn empty(v: &Vec<int>) {
+    assert!(v.is_empty());
+}
+
+#[test]
+fn plain() {}
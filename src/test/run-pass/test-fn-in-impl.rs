@@ -0,0 +1,35 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+
+// compile-flags: --test
+// ignore-pretty: does not work well with `--test`
+
+struct Foo;
+
+impl Foo {
+    #[test]
+    fn in_impl() {}
+
+    #[test]
+    #[should_fail]
+    fn fails_in_impl() {
+        fail!()
+    }
+}
+
+mod m {
+    struct Bar;
+
+    impl Bar {
+        #[test]
+        fn in_nested_impl() {}
+    }
+}
@@ -0,0 +1,30 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+
+// compile-flags: --test
+// ignore-pretty: does not work well with `--test`
+
+#![test_harness(args = "harness_args::get")]
+
+mod harness_args {
+    // run only the test which is expected to pass
+    pub fn get() -> Vec<String> {
+        vec!["test-harness-args".to_string(), "runs".to_string()]
+    }
+}
+
+#[test]
+fn runs() {}
+
+#[test]
+fn filtered_out() {
+    fail!("the arguments should have filtered this out");
+}
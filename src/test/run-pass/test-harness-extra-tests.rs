@@ -0,0 +1,51 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+// ignore-pretty: does not work well with `--test`
+
+#![test_harness(extra_tests = "doctests::TESTS")]
+
+extern crate test;
+
+mod doctests {
+    use test::{TestDescAndFn, TestDesc, StaticTestName, StaticTestFn, No};
+
+    fn doctest() {}
+
+    pub static TESTS: &'static [TestDescAndFn] = &[
+        TestDescAndFn {
+            desc: TestDesc {
+                name: StaticTestName("doctest"),
+                ignore: false,
+                should_fail: No,
+                ignore_reason: None,
+                timeout_ms: None,
+                source_file: "",
+                start_line: 0,
+                iterations: None,
+                groups: &[],
+                description: None,
+                allow_fail: false,
+                retries: 0,
+                module_path: "",
+                weight: 1,
+                serial: false,
+                order_group: None,
+                run_after: &[],
+                bench_unit: None,
+            },
+            testfn: StaticTestFn(doctest),
+        },
+    ];
+}
+
+#[test]
+fn a_test() {}
@@ -0,0 +1,29 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+// ignore-pretty: does not work well with `--test`
+
+#![test_harness(main = "setup::run")]
+
+extern crate test;
+
+mod setup {
+    use std::os;
+    use test;
+
+    pub fn run(tests: &[test::TestDescAndFn]) {
+        assert_eq!(tests.len(), 1);
+        test::test_main_static(os::args().as_slice(), tests);
+    }
+}
+
+#[test]
+fn a_test() {}
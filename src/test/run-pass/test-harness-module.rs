@@ -0,0 +1,32 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+// ignore-pretty: does not work well with `--test`
+
+#![test_harness(module = "my_tests")]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate test;
+
+// The generated module can be named directly.
+#[allow(dead_code)]
+fn harness_main() -> fn() {
+    ::my_tests::main
+}
+
+#[test]
+fn a_test() {}
+
+#[test]
+fn describes_the_tests() {
+    let tests: &'static [test::TestDescAndFn] = ::my_tests::describe();
+    assert_eq!(tests.len(), 2);
+}
@@ -0,0 +1,34 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+// ignore-pretty: does not work well with `--test`
+
+// Without the harness's main, the crate's own is the entry point, and runs
+// the tests through the named test module.
+
+#![test_harness(no_main, module = "my_tests")]
+
+extern crate test;
+
+use std::os;
+use std::sync::atomic::{AtomicBool, SeqCst, INIT_ATOMIC_BOOL};
+
+static mut HOST_MAIN_RAN: AtomicBool = INIT_ATOMIC_BOOL;
+
+fn main() {
+    unsafe { HOST_MAIN_RAN.store(true, SeqCst); }
+    test::test_main_static(os::args().as_slice(), ::my_tests::describe());
+}
+
+#[test]
+fn run_from_the_crates_main() {
+    assert!(unsafe { HOST_MAIN_RAN.load(SeqCst) });
+}
@@ -0,0 +1,29 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+// ignore-pretty: does not work well with `--test`
+
+#![test_harness(wrap = "tracing::trace_test")]
+
+mod tracing {
+    pub fn trace_test(name: &'static str, test: fn()) {
+        assert!(name.starts_with("traced"));
+        test();
+    }
+}
+
+#[test]
+fn traced_test() {}
+
+#[test]
+fn traced_result() -> Result<(), String> {
+    Ok(())
+}
@@ -0,0 +1,43 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+// ignore-pretty: does not work well with `--test`
+
+// Default methods without `self` in a trait are collected as tests.
+
+#![deny(unused_variable)]
+
+trait Fixture {
+    #[test]
+    fn in_trait() {}
+
+    #[test]
+    #[should_fail]
+    fn fails_in_trait() {
+        fail!()
+    }
+
+    // the copy the harness calls keeps the #[allow]
+    #[test]
+    #[allow(unused_variable)]
+    fn keeps_attributes() {
+        let unused = 1i;
+    }
+
+    fn not_a_test(&self) {}
+}
+
+mod m {
+    pub trait Nested {
+        #[test]
+        fn in_nested_trait() {}
+    }
+}
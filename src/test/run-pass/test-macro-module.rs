@@ -0,0 +1,57 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+// ignore-pretty: does not work well with `--test`
+
+// Tests in modules made by macros are reexported under the idents they were
+// collected with, so the harness can reach every one of them.
+
+#![feature(macro_rules)]
+#![test_harness(module = "my_tests")]
+
+extern crate test;
+
+macro_rules! test_mod(
+    ($name:ident, $value:expr) => (
+        mod $name {
+            #[test]
+            fn value_is_positive() {
+                assert!($value > 0i);
+            }
+
+            mod inner {
+                #[test]
+                fn nested() {}
+            }
+        }
+    )
+)
+
+test_mod!(first, 1i)
+test_mod!(second, 2i)
+
+// a module named by the macro itself, rather than by its caller
+macro_rules! fixed_mod(
+    () => (
+        mod from_macro {
+            #[test]
+            fn in_fixed_module() {}
+        }
+    )
+)
+
+fixed_mod!()
+
+#[test]
+fn every_test_is_described() {
+    let tests: &'static [test::TestDescAndFn] = ::my_tests::describe();
+    assert_eq!(tests.len(), 6);
+}
@@ -0,0 +1,51 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+// ignore-pretty: does not work well with `--test`
+
+// Tests in deeply nested modules, next to sibling modules with and without
+// tests, are all reexported to the harness.
+
+#[test]
+fn top() {}
+
+mod a {
+    #[test]
+    fn in_a() {}
+
+    mod b {
+        mod c {
+            mod d {
+                #[test]
+                fn in_d() {}
+            }
+
+            mod untested {
+                pub fn helper() {}
+            }
+
+            #[test]
+            fn in_c() {
+                untested::helper();
+            }
+        }
+    }
+
+    mod sibling {
+        #[test]
+        fn in_sibling() {}
+    }
+}
+
+mod untested_sibling {
+    #[allow(dead_code)]
+    fn not_a_test() {}
+}
@@ -0,0 +1,34 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+
+// compile-flags: --test
+// ignore-pretty: does not work well with `--test`
+
+// Tests run after the group they're ordered after has finished.
+
+use std::sync::atomic::{AtomicUint, SeqCst, INIT_ATOMIC_UINT};
+
+static mut SET_UP: AtomicUint = INIT_ATOMIC_UINT;
+
+#[test(group = "setup")]
+fn set_up_one() {
+    unsafe { SET_UP.fetch_add(1, SeqCst); }
+}
+
+#[test(group = "setup")]
+fn set_up_two() {
+    unsafe { SET_UP.fetch_add(1, SeqCst); }
+}
+
+#[test(after = "setup")]
+fn uses_set_up() {
+    assert_eq!(unsafe { SET_UP.load(SeqCst) }, 2);
+}
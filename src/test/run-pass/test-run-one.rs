@@ -0,0 +1,31 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test -Z test-run-one
+// ignore-pretty: does not work well with `--test`
+
+#![test_harness(module = "my_tests")]
+
+#[test]
+fn passes() {}
+
+// ignored, so that only run_one runs it
+#[test]
+#[ignore]
+fn fails() {
+    fail!("run by name");
+}
+
+#[test]
+fn runs_tests_by_name() {
+    assert!(::my_tests::run_one("passes"));
+    assert!(!::my_tests::run_one("fails"));
+    assert!(!::my_tests::run_one("no_such_test"));
+}
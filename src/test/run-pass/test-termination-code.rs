@@ -0,0 +1,47 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// compile-flags: --test
+// ignore-pretty: does not work well with `--test`
+
+// Any test::Termination can be returned, and a non-zero code fails the test.
+
+extern crate test;
+
+struct Status(int);
+
+impl test::Termination for Status {
+    fn report(self) -> int {
+        let Status(code) = self;
+        code
+    }
+}
+
+#[test]
+fn returns_zero() -> int {
+    0
+}
+
+#[test]
+#[should_fail(expected = "test returned failure code 3")]
+fn returns_three() -> int {
+    3
+}
+
+#[test]
+fn returns_passing_status() -> Status {
+    Status(0)
+}
+
+#[test]
+#[should_fail(expected = "test returned failure code 1")]
+fn returns_failing_status() -> Status {
+    Status(1)
+}